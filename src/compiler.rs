@@ -0,0 +1,688 @@
+//! Compiler that converts parsed Pravda program to bytecode
+use crate::format::Spec;
+use crate::lexer::Span;
+use crate::number;
+use crate::parser::{Node, NodeKind, Part, Stmt, StmtKind};
+use crate::{Type, LAMBDA};
+use num_complex::Complex64;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Instruction of the bytecode
+/// Each expression pushes its result value onto the stack
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    /// Push the value in the constant pool
+    Constant(u32),
+    /// Push the local variable in the slot
+    /// If it's not defined yet, the fallback instruction looks up the outer scope
+    GetLocal(u32, u32),
+    /// Push the variable that's captured by the closure, with the fallback instruction
+    GetUpvalue(u32, u32),
+    /// Push the global variable, or the symbol if it's not defined
+    /// If it's head of the calling, the file of the name is loaded at the span
+    GetGlobal { name: u32, callee: Option<u32> },
+    /// Define the local variable by the top value
+    DefineLocal(u32),
+    /// Define the global variable by the top value
+    DefineGlobal(u32),
+    /// Reset the local variables in the range when the block is started
+    ClearLocals(u32, u32),
+    /// Discard the top value
+    Pop,
+    /// Collect the values into the list
+    List(u32),
+    /// Append the top value to the list below it
+    Append,
+    /// Append the elements of the top value to the list below it
+    Extend,
+    /// Convert the top value to string by the format spec
+    Stringify { spec: u32, span: u32 },
+    /// Join the strings into one
+    Concat(u32),
+    /// Collect the values into the struct that has the fields
+    Struct(u32),
+    /// Collect the pairs of key and value into the dictionary
+    /// The spans of the keys are from the index of `span`
+    Dict { count: u32, span: u32 },
+    /// Convert the list of the elements into the set
+    Set(u32),
+    /// Create the function by the argument patterns on the stack
+    /// If it's the clause, the function to add it is below the patterns
+    Closure {
+        proto: u32,
+        args: u32,
+        name: u32,
+        clause: bool,
+        span: u32,
+    },
+    /// Name the lambda after the variable that's defined by it
+    Rename(u32),
+    /// Jump if the top value is not function, so that the value becomes the result
+    JumpIfNotFunction(u32),
+    /// Jump unconditionally
+    Jump(u32),
+    /// Call the function with the arguments on the stack, or the list of them if it's spread
+    /// The result is called again without arguments as many times as `invoke`
+    Call {
+        args: u32,
+        spread: bool,
+        tail: bool,
+        invoke: u32,
+        span: u32,
+    },
+    /// Call the top value without arguments if it's function, as many times as `times`
+    Invoke { times: u32, tail: bool, span: u32 },
+    /// Finish the lazy code and go back to where it's evaluated
+    EndThunk,
+    /// Finish the function and go back to the caller
+    Return,
+}
+
+/// Variable of the outer function that the closure captures
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Capture {
+    /// Local variable of the outer function
+    Local(u32),
+    /// Variable that's captured by the outer function
+    Upvalue(u32),
+}
+
+/// Compiled code of the function or the program
+#[derive(Debug, Default)]
+pub struct Proto {
+    pub code: Vec<Op>,
+    pub constants: Vec<Type>,
+    /// Names of the global variables
+    pub names: Vec<String>,
+    /// Locations of the callings
+    pub spans: Vec<Span>,
+    /// Instructions to look up the outer scope if the variable isn't defined yet
+    pub fallbacks: Vec<Op>,
+    /// Format specs of the interpolated strings
+    pub specs: Vec<Option<Spec>>,
+    /// Field names of the struct literals
+    pub shapes: Vec<Vec<String>>,
+    /// Functions that are defined in this code
+    pub protos: Vec<Rc<Proto>>,
+    /// Names of the local variables, the index is the slot
+    pub locals: Vec<String>,
+    /// Is the local variable captured by the closure?
+    pub captured: Vec<bool>,
+    /// Slot to bind for each argument pattern
+    pub params: Vec<Option<u32>>,
+    /// Variables of the outer function that this function captures
+    pub captures: Vec<Capture>,
+    /// Names of the captured variables
+    pub upvalues: Vec<String>,
+    /// Lazy codes and where they start, and the index is the id of the code
+    /// The code is held here, so that it's the same value as one the control function returns
+    pub thunks: Vec<(Type, u32)>,
+}
+
+/// Scope of the variables while compiling
+enum Scope {
+    /// Variables are defined in the environment at runtime
+    Global,
+    /// Variables are in the slots of the function
+    Local(HashMap<String, u32>),
+}
+
+/// Where the variable is found
+enum Found {
+    Global,
+    Local {
+        state: usize,
+        scope: usize,
+        slot: u32,
+    },
+}
+
+/// Function that's being compiled
+struct State {
+    proto: Proto,
+    scopes: Vec<Scope>,
+}
+
+struct Compiler {
+    /// Functions from the outermost to the current one
+    states: Vec<State>,
+}
+
+/// Compile the program that runs in the global scope
+pub fn compile(program: &[Stmt]) -> Rc<Proto> {
+    let mut compiler = Compiler {
+        states: vec![State {
+            proto: Proto::default(),
+            scopes: vec![Scope::Global],
+        }],
+    };
+    compiler.program(program, false);
+    compiler.emit(Op::Return);
+    Rc::new(compiler.states.pop().unwrap().proto)
+}
+
+/// Names of the variables and functions that the program defines
+fn defined(program: &[Stmt]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for line in program {
+        if let StmtKind::Define(name, _) | StmtKind::Function(name, ..) = &line.kind {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+    }
+    names
+}
+
+/// Names of the symbols quoted in the code, such as `@acc` of `reduce`,
+/// since the built-in function may define them in the scope of the caller
+fn quoted(node: &Node, names: &mut Vec<String>) {
+    match &node.kind {
+        NodeKind::Lazy(term) => match &term.kind {
+            NodeKind::Symbol(name) => names.push(name.clone()),
+            _ => quoted(term, names),
+        },
+        NodeKind::Expr(terms) | NodeKind::List(terms) | NodeKind::Set(terms) => {
+            terms.iter().for_each(|term| quoted(term, names))
+        }
+        NodeKind::Block(program) => {
+            for line in program.iter() {
+                if let StmtKind::Expr(node) | StmtKind::Define(_, node) = &line.kind {
+                    quoted(node, names);
+                }
+            }
+        }
+        NodeKind::Struct(fields) => fields.iter().for_each(|(_, value)| quoted(value, names)),
+        NodeKind::Dict(entries) => entries.iter().for_each(|(key, value)| {
+            quoted(key, names);
+            quoted(value, names);
+        }),
+        NodeKind::Spread(term) => quoted(term, names),
+        NodeKind::Format(parts) => {
+            for part in parts {
+                if let Part::Value(node, _) = part {
+                    quoted(node, names);
+                }
+            }
+        }
+        // The lambda has its own scope
+        _ => {}
+    }
+}
+
+/// Name of the variable that the argument pattern binds
+fn param_name(define: &Node) -> Option<String> {
+    match &define.kind {
+        NodeKind::Symbol(name) => Some(name.clone()),
+        NodeKind::Spread(term) => Some(term.to_string()),
+        NodeKind::Lazy(term) => match &term.kind {
+            NodeKind::Symbol(name) => Some(name.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl Compiler {
+    fn proto(&mut self) -> &mut Proto {
+        &mut self.states.last_mut().unwrap().proto
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let code = &mut self.proto().code;
+        code.push(op);
+        code.len() - 1
+    }
+
+    /// Address of the next instruction
+    fn here(&mut self) -> u32 {
+        self.proto().code.len() as u32
+    }
+
+    /// Set the destination of the jump to the next instruction
+    fn patch(&mut self, jump: usize) {
+        let here = self.here();
+        match &mut self.proto().code[jump] {
+            Op::Jump(target) | Op::JumpIfNotFunction(target) => *target = here,
+            _ => unreachable!(),
+        }
+    }
+
+    fn constant(&mut self, value: Type) -> u32 {
+        let constants = &mut self.proto().constants;
+        constants.push(value);
+        constants.len() as u32 - 1
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        let names = &mut self.proto().names;
+        match names.iter().position(|i| i == name) {
+            Some(index) => index as u32,
+            None => {
+                names.push(name.to_string());
+                names.len() as u32 - 1
+            }
+        }
+    }
+
+    fn span(&mut self, span: &Span) -> u32 {
+        let spans = &mut self.proto().spans;
+        spans.push(span.clone());
+        spans.len() as u32 - 1
+    }
+
+    /// Start the scope that has the variables, and return the range of their slots
+    fn begin_scope(&mut self, names: Vec<String>) -> (u32, u32) {
+        let state = self.states.last_mut().unwrap();
+        let start = state.proto.locals.len() as u32;
+        let mut slots = HashMap::new();
+        for name in names {
+            slots.insert(name.clone(), state.proto.locals.len() as u32);
+            state.proto.locals.push(name);
+            state.proto.captured.push(false);
+        }
+        state.scopes.push(Scope::Local(slots));
+        (start, state.proto.locals.len() as u32)
+    }
+
+    fn end_scope(&mut self) {
+        self.states.last_mut().unwrap().scopes.pop();
+    }
+
+    /// Find the variable from the scopes below the index of the function to the outer ones
+    fn find(&self, mut state: usize, mut below: usize, name: &str) -> Found {
+        loop {
+            for scope in (0..below).rev() {
+                match &self.states[state].scopes[scope] {
+                    Scope::Global => return Found::Global,
+                    Scope::Local(slots) => {
+                        if let Some(slot) = slots.get(name) {
+                            return Found::Local {
+                                state,
+                                scope,
+                                slot: *slot,
+                            };
+                        }
+                    }
+                }
+            }
+            if state == 0 {
+                return Found::Global;
+            }
+            state -= 1;
+            below = self.states[state].scopes.len();
+        }
+    }
+
+    /// Instruction to read the variable in the current function
+    fn access(&mut self, name: &str, callee: Option<u32>) -> Op {
+        let state = self.states.len() - 1;
+        let below = self.states[state].scopes.len();
+        self.access_from(state, below, name, callee)
+    }
+
+    fn access_from(&mut self, state: usize, below: usize, name: &str, callee: Option<u32>) -> Op {
+        match self.find(state, below, name) {
+            Found::Global => Op::GetGlobal {
+                name: self.name(name),
+                callee,
+            },
+            Found::Local { state, scope, slot } => {
+                let fallback = self.access_from(state, scope, name, callee);
+                let fallbacks = &mut self.proto().fallbacks;
+                fallbacks.push(fallback);
+                let fallback = fallbacks.len() as u32 - 1;
+                if state == self.states.len() - 1 {
+                    Op::GetLocal(slot, fallback)
+                } else {
+                    Op::GetUpvalue(self.capture(state, slot, name), fallback)
+                }
+            }
+        }
+    }
+
+    /// Capture the local variable of the outer function through the nested ones
+    fn capture(&mut self, state: usize, slot: u32, name: &str) -> u32 {
+        self.states[state].proto.captured[slot as usize] = true;
+        let mut capture = Capture::Local(slot);
+        let mut index = 0;
+        for inner in &mut self.states[state + 1..] {
+            let proto = &mut inner.proto;
+            index = match proto.captures.iter().position(|i| *i == capture) {
+                Some(index) => index as u32,
+                None => {
+                    proto.captures.push(capture);
+                    proto.upvalues.push(name.to_string());
+                    proto.captures.len() as u32 - 1
+                }
+            };
+            capture = Capture::Upvalue(index);
+        }
+        index
+    }
+
+    /// Define the variable in the current scope by the top value
+    fn define(&mut self, name: &str) {
+        let op = match self.states.last().unwrap().scopes.last().unwrap() {
+            Scope::Global => Op::DefineGlobal(self.name(name)),
+            Scope::Local(slots) => Op::DefineLocal(slots[name]),
+        };
+        self.emit(op);
+    }
+
+    /// Compile lines of the program, and the value of the last line is left
+    fn program(&mut self, program: &[Stmt], tail: bool) {
+        if program.is_empty() {
+            let null = self.constant(Type::Null);
+            self.emit(Op::Constant(null));
+        }
+        for (index, line) in program.iter().enumerate() {
+            let last = index + 1 == program.len();
+            match &line.kind {
+                StmtKind::Function(name, args, body) => {
+                    // The function to add the clause, if it's already defined
+                    let exist = self.access(name, None);
+                    self.emit(exist);
+                    for arg in args {
+                        self.pattern(arg);
+                    }
+                    let proto = self.function(args, body);
+                    let op = Op::Closure {
+                        proto,
+                        args: args.len() as u32,
+                        name: self.name(name),
+                        clause: true,
+                        span: self.span(&line.span),
+                    };
+                    self.emit(op);
+                    self.define(name);
+                }
+                StmtKind::Define(name, value) => {
+                    self.expr(value, false, 0);
+                    let name_index = self.name(name);
+                    self.emit(Op::Rename(name_index));
+                    self.define(name);
+                }
+                StmtKind::Expr(expr) => self.expr(expr, tail && last, 0),
+            }
+            if !last {
+                self.emit(Op::Pop);
+            }
+        }
+    }
+
+    /// Compile the block that has its own scope
+    fn block(&mut self, program: &[Stmt], tail: bool) {
+        let (start, end) = self.begin_scope(defined(program));
+        if start < end {
+            self.emit(Op::ClearLocals(start, end));
+        }
+        self.program(program, tail);
+        // The scope of the caller is linked to the slots, so they're hidden after the block
+        if start < end && !tail {
+            self.emit(Op::ClearLocals(start, end));
+        }
+        self.end_scope();
+    }
+
+    /// Compile the function, and return its index
+    /// The code block of the body is run in the scope of the arguments
+    fn function(&mut self, args: &[Node], body: &Node) -> u32 {
+        let params: Vec<Option<String>> = args.iter().map(param_name).collect();
+        let block = match &body.kind {
+            NodeKind::Expr(terms) if terms.len() == 1 => match &terms[0].kind {
+                NodeKind::Block(program) => Some(program.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let mut names: Vec<String> = params.iter().flatten().cloned().collect();
+        if let Some(program) = &block {
+            names.extend(defined(program));
+        }
+        quoted(body, &mut names);
+        // The argument may have the same name as the variable, and the first one is kept
+        let mut seen = HashSet::new();
+        names.retain(|name| seen.insert(name.clone()));
+
+        self.states.push(State {
+            proto: Proto::default(),
+            scopes: vec![],
+        });
+        self.begin_scope(names);
+        let Some(Scope::Local(slots)) = self.states.last().unwrap().scopes.last() else {
+            unreachable!()
+        };
+        let params = params
+            .iter()
+            .map(|name| name.as_ref().map(|name| slots[name]))
+            .collect();
+        self.proto().params = params;
+
+        match &block {
+            Some(program) => self.program(program, true),
+            None => self.expr(body, true, 0),
+        }
+        self.emit(Op::Return);
+
+        let proto = self.states.pop().unwrap().proto;
+        let protos = &mut self.proto().protos;
+        protos.push(Rc::new(proto));
+        protos.len() as u32 - 1
+    }
+
+    /// Compile the argument pattern of the function
+    fn pattern(&mut self, define: &Node) {
+        match &define.kind {
+            NodeKind::Symbol(name) => {
+                let symbol = self.constant(Type::Symbol(name.to_string()));
+                self.emit(Op::Constant(symbol));
+            }
+            // Mutable length argument
+            NodeKind::Spread(term) => {
+                let symbol = self.constant(Type::Symbol(format!("~{term}")));
+                self.emit(Op::Constant(symbol));
+            }
+            _ => self.term(define),
+        }
+    }
+
+    /// Compile the expression
+    /// If it's in tail position, calling user-defined function replaces the current one
+    fn expr(&mut self, expr: &Node, tail: bool, invoke: u32) {
+        let NodeKind::Expr(terms) = &expr.kind else {
+            self.term(expr);
+            self.invoke(invoke, tail, &expr.span);
+            return;
+        };
+        let Some(head) = terms.first() else {
+            let null = self.constant(Type::Null);
+            self.emit(Op::Constant(null));
+            return;
+        };
+        let args = &terms[1..terms.len()];
+
+        match &head.kind {
+            NodeKind::Symbol(name) => {
+                let span = self.span(&head.span);
+                let op = self.access(name, Some(span));
+                self.emit(op);
+                self.call(args, expr, tail, invoke);
+            }
+            // The result of the calling is called again after it's finished
+            NodeKind::Expr(_) if args.is_empty() => self.expr(head, tail, invoke + 1),
+            NodeKind::Lambda(..) | NodeKind::Block(_) | NodeKind::Expr(_) => {
+                // Evaluate the code, and if it's function, call it
+                self.term(head);
+                self.call(args, expr, tail, invoke);
+            }
+            _ if args.is_empty() => {
+                self.term(head);
+                self.invoke(invoke, tail, &expr.span);
+            }
+            // If there's multiple value, return it as a list
+            _ => self.list(terms),
+        }
+    }
+
+    /// Compile calling the function on the stack, if it's function
+    fn call(&mut self, args: &[Node], expr: &Node, tail: bool, invoke: u32) {
+        let jump = self.emit(Op::JumpIfNotFunction(0));
+        let spread = self.arguments(args);
+        let op = Op::Call {
+            args: args.len() as u32,
+            spread,
+            tail,
+            invoke,
+            span: self.span(&expr.span),
+        };
+        self.emit(op);
+        self.patch(jump);
+    }
+
+    fn invoke(&mut self, times: u32, tail: bool, span: &Span) {
+        if times > 0 {
+            let span = self.span(span);
+            self.emit(Op::Invoke { times, tail, span });
+        }
+    }
+
+    /// Compile the arguments, and return whether they're collected in a list by spreading
+    fn arguments(&mut self, terms: &[Node]) -> bool {
+        if !terms.iter().any(|i| matches!(i.kind, NodeKind::Spread(_))) {
+            for term in terms {
+                self.term(term);
+            }
+            return false;
+        }
+
+        self.emit(Op::List(0));
+        for term in terms {
+            if let NodeKind::Spread(term) = &term.kind {
+                // Expand the list as argument
+                self.term(term);
+                self.emit(Op::Extend);
+            } else {
+                self.term(term);
+                self.emit(Op::Append);
+            }
+        }
+        true
+    }
+
+    fn list(&mut self, items: &[Node]) {
+        if !self.arguments(items) {
+            self.emit(Op::List(items.len() as u32));
+        }
+    }
+
+    /// Compile the term that's element of the expression
+    fn term(&mut self, term: &Node) {
+        match &term.kind {
+            NodeKind::Integer(value) => self.push(Type::Integer(value.clone())),
+            NodeKind::Rational(value) => self.push(number::from_ratio(value.clone())),
+            NodeKind::Imaginary(value) => self.push(Type::Complex(Complex64::new(0.0, *value))),
+            NodeKind::Number(value) => self.push(Type::Number(*value)),
+            NodeKind::String(value) => self.push(Type::String(value.to_string())),
+            NodeKind::Bool(value) => self.push(Type::Bool(*value)),
+            NodeKind::Null => self.push(Type::Null),
+            NodeKind::Symbol(name) => {
+                let op = self.access(name, None);
+                self.emit(op);
+            }
+            NodeKind::Expr(_) => self.expr(term, false, 0),
+            NodeKind::Block(program) => self.block(program, false),
+            NodeKind::List(items) => self.list(items),
+            NodeKind::Lambda(args, body) => {
+                for arg in args {
+                    self.pattern(arg);
+                }
+                let proto = self.function(args, body);
+                let op = Op::Closure {
+                    proto,
+                    args: args.len() as u32,
+                    name: self.name(LAMBDA),
+                    clause: false,
+                    span: self.span(&term.span),
+                };
+                self.emit(op);
+            }
+            NodeKind::Struct(fields) => {
+                for (_, value) in fields {
+                    self.expr(value, false, 0);
+                }
+                let shapes = &mut self.proto().shapes;
+                shapes.push(fields.iter().map(|(name, _)| name.clone()).collect());
+                let shape = shapes.len() as u32 - 1;
+                self.emit(Op::Struct(shape));
+            }
+            NodeKind::Dict(entries) => {
+                for (key, value) in entries {
+                    self.expr(key, false, 0);
+                    self.expr(value, false, 0);
+                }
+                let span = self.proto().spans.len() as u32;
+                for (key, _) in entries {
+                    self.span(&key.span);
+                }
+                let count = entries.len() as u32;
+                self.emit(Op::Dict { count, span });
+            }
+            NodeKind::Set(items) => {
+                self.list(items);
+                let span = self.span(&term.span);
+                self.emit(Op::Set(span));
+            }
+            NodeKind::Lazy(inner) => match &inner.kind {
+                // Processing of lazy evaluate expression
+                NodeKind::Expr(_) => {
+                    let code = Type::Expr(inner.clone());
+                    self.thunk(code.clone(), |compiler| compiler.expr(inner, true, 0));
+                    self.push(code);
+                }
+                NodeKind::Block(program) => {
+                    let code = Type::Block(program.clone());
+                    self.thunk(code.clone(), |compiler| compiler.block(program, true));
+                    self.push(code);
+                }
+                NodeKind::Symbol(name) => self.push(Type::Symbol(name.to_string())),
+                _ => self.term(inner),
+            },
+            NodeKind::Spread(inner) => self.term(inner),
+            NodeKind::Format(parts) => {
+                for part in parts {
+                    match part {
+                        Part::Text(text) => self.push(Type::String(text.clone())),
+                        Part::Value(expr, spec) => {
+                            self.expr(expr, false, 0);
+                            let specs = &mut self.proto().specs;
+                            specs.push(spec.clone());
+                            let spec = specs.len() as u32 - 1;
+                            let span = self.span(&expr.span);
+                            self.emit(Op::Stringify { spec, span });
+                        }
+                    }
+                }
+                self.emit(Op::Concat(parts.len() as u32));
+            }
+        }
+    }
+
+    fn push(&mut self, value: Type) {
+        let index = self.constant(value);
+        self.emit(Op::Constant(index));
+    }
+
+    /// Compile the lazy code where it's written, so that it can access the same variables
+    /// It's skipped until the control function such as `if` evaluates it
+    fn thunk(&mut self, code: Type, body: impl FnOnce(&mut Compiler)) {
+        let jump = self.emit(Op::Jump(0));
+        let start = self.here();
+        body(self);
+        self.emit(Op::EndThunk);
+        self.patch(jump);
+        self.proto().thunks.push((code, start));
+    }
+}
@@ -0,0 +1,76 @@
+//! Diagnostic messages that show where the problem is in the source code
+use crate::lexer::Span;
+use std::fmt;
+
+/// Error or warning message with its location
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl ToString, span: Span) -> Diagnostic {
+        Diagnostic {
+            message: message.to_string(),
+            span,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error! {}\n{}", self.message, excerpt(&self.span))
+    }
+}
+
+/// Show the warning with its location, that doesn't stop the program
+pub fn warn(message: &str, span: &Span) {
+    eprintln!("Warning! {message}\n{}", excerpt(span));
+}
+
+/// Show the source line of the span and underline it by caret
+/// ```
+///  --> script.pvd:3:5
+///   |
+/// 3 | x = (+ 1 "a")
+///   |     ^^^^^^^^^
+/// ```
+pub fn excerpt(span: &Span) -> String {
+    let code = &span.source.code;
+    let (line_start, line_end) = line_range(span);
+    let line = source_line(span);
+
+    // Keep tabs so that the caret is aligned with the source line
+    let indent: String = code[line_start..span.start]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = code[span.start..span.end.min(line_end).max(span.start)]
+        .chars()
+        .count()
+        .max(1);
+
+    let number = span.line.to_string();
+    let margin = " ".repeat(number.len());
+    format!(
+        "{margin}--> {span}\n{margin} |\n{number} | {line}\n{margin} | {indent}{}",
+        "^".repeat(width)
+    )
+}
+
+/// Byte range of the line that the span starts
+fn line_range(span: &Span) -> (usize, usize) {
+    let code = &span.source.code;
+    let line_start = code[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = code[span.start..]
+        .find('\n')
+        .map_or(code.len(), |i| span.start + i);
+    (line_start, line_end)
+}
+
+/// Text of the source line that the span starts
+pub fn source_line(span: &Span) -> &str {
+    let (line_start, line_end) = line_range(span);
+    span.source.code[line_start..line_end].trim_end_matches('\r')
+}
@@ -0,0 +1,148 @@
+//! Dictionary of Pravda, that's ordered by the keys
+//!
+//! The key should be hashable value, that's null, bool, number, string, symbol
+//! or list of them. The keys are ordered in the same way as the values (see `order`),
+//! so `1` and `1.0` are same key
+use crate::error::{ErrorKind, PravdaError};
+use crate::number::{self, Int};
+use crate::{type_error, Type};
+use im_rc::{vector, OrdMap};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use std::cmp::Ordering;
+
+/// Entries of the dictionary
+pub type Dict = OrdMap<Key, Type>;
+
+/// Hashable value that's used as the key of the dictionary
+#[derive(Clone, Debug)]
+pub enum Key {
+    Null,
+    Bool(bool),
+    Integer(Int),
+    Rational(BigRational),
+    Number(f64),
+    Complex(Complex64),
+    String(String),
+    Symbol(String),
+    List(Vec<Key>),
+}
+
+impl Key {
+    /// Make the key from the value, or error if it's not hashable
+    pub fn new(value: &Type) -> Result<Key, PravdaError> {
+        Ok(match value {
+            Type::Null => Key::Null,
+            Type::Bool(value) => Key::Bool(*value),
+            Type::Integer(value) => Key::Integer(value.clone()),
+            Type::Rational(value) => Key::Rational(value.clone()),
+            Type::Number(value) => Key::Number(*value),
+            Type::Complex(value) => Key::Complex(*value),
+            Type::String(value) => Key::String(value.clone()),
+            Type::Symbol(value) => Key::Symbol(value.clone()),
+            Type::List(items) => Key::List(items.iter().map(Key::new).collect::<Result<_, _>>()?),
+            other => {
+                return Err(PravdaError::new(
+                    ErrorKind::Type,
+                    format!("expected hashable value, but found {}", other.type_name()),
+                ))
+            }
+        })
+    }
+
+    /// Convert the key back to the value
+    pub fn to_type(&self) -> Type {
+        match self {
+            Key::Null => Type::Null,
+            Key::Bool(value) => Type::Bool(*value),
+            Key::Integer(value) => Type::Integer(value.clone()),
+            Key::Rational(value) => Type::Rational(value.clone()),
+            Key::Number(value) => Type::Number(*value),
+            Key::Complex(value) => Type::Complex(*value),
+            Key::String(value) => Type::String(value.clone()),
+            Key::Symbol(value) => Type::Symbol(value.clone()),
+            Key::List(items) => Type::List(items.iter().map(Key::to_type).collect()),
+        }
+    }
+
+    /// Rank of the type in the order of the keys
+    fn rank(&self) -> u8 {
+        match self {
+            Key::Null => 0,
+            Key::Bool(_) => 1,
+            Key::Integer(_) | Key::Rational(_) | Key::Number(_) | Key::Complex(_) => 2,
+            Key::String(_) => 3,
+            Key::Symbol(_) => 4,
+            Key::List(_) => 5,
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Key) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Key) -> Ordering {
+        match (self, other) {
+            (Key::Bool(a), Key::Bool(b)) => a.cmp(b),
+            (Key::String(a), Key::String(b)) | (Key::Symbol(a), Key::Symbol(b)) => a.cmp(b),
+            (Key::List(a), Key::List(b)) => a.cmp(b),
+            _ if self.rank() == 2 && other.rank() == 2 => {
+                number::total_cmp(&self.to_type(), &other.to_type())
+            }
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+/// Get the dictionary from the value, or error if it's other type
+pub fn dict(value: &Type) -> Result<&Dict, PravdaError> {
+    match value {
+        Type::Dict(dict) => Ok(dict),
+        other => Err(type_error("dict", other)),
+    }
+}
+
+/// Split the entry `[key value]` into the key and value
+pub fn entry(value: &Type) -> Result<(Key, Type), PravdaError> {
+    match value {
+        Type::List(pair) if pair.len() == 2 => Ok((Key::new(&pair[0])?, pair[1].clone())),
+        other => Err(PravdaError::new(
+            ErrorKind::Value,
+            format!(
+                "expected entry `[key value]`, but found {}",
+                other.get_symbol()
+            ),
+        )),
+    }
+}
+
+/// Make the list of the entry `[key value]`
+pub fn to_entry(key: &Key, value: &Type) -> Type {
+    Type::List(vector![key.to_type(), value.clone()])
+}
+
+/// Show the dictionary as the literal, e.g. `dict{ "a" -> 1; "b" -> 2 }`
+pub fn dict_to_string(dict: &Dict) -> String {
+    if dict.is_empty() {
+        return "dict{}".to_string();
+    }
+    format!(
+        "dict{{ {} }}",
+        dict.iter()
+            .map(|(key, value)| format!("{} -> {}", key.to_type().get_symbol(), value.get_symbol()))
+            .collect::<Vec<String>>()
+            .join("; ")
+    )
+}
@@ -0,0 +1,164 @@
+//! Environment that has variables and functions in the scope
+use crate::{Function, Type};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+/// Variable that's shared between the scope, the VM frame and the closures that capture it
+pub type Shared = Rc<RefCell<Option<Type>>>;
+
+/// Scope where the user-defined function is defined
+/// The function that's stored in that scope captures it weakly, so that they don't make a cycle,
+/// and it becomes strong again when the function is taken out of the scope
+#[derive(Clone, Debug)]
+pub enum Captured {
+    Strong(Rc<Env>),
+    Weak(Weak<Env>),
+}
+
+impl Captured {
+    /// Get the scope, that's alive while the function is reachable from it
+    pub fn scope(&self) -> Rc<Env> {
+        match self {
+            Captured::Strong(scope) => scope.clone(),
+            Captured::Weak(scope) => scope
+                .upgrade()
+                .expect("the scope is alive while its function is taken out"),
+        }
+    }
+
+    /// Address of the scope to identify the function
+    pub fn address(&self) -> usize {
+        match self {
+            Captured::Strong(scope) => Rc::as_ptr(scope) as usize,
+            Captured::Weak(scope) => scope.as_ptr() as usize,
+        }
+    }
+}
+
+/// Frame of the scope that's linked to the outer one
+/// Function captures the environment where it's defined by reference,
+/// so creating a new scope doesn't copy any variable
+pub struct Env {
+    /// Variables defined in this scope
+    vars: RefCell<HashMap<String, Type>>,
+    /// Variables whose values are stored in the cells owned by the VM frame
+    linked: HashMap<String, Shared>,
+    /// Outer scope to look up if the variable isn't found
+    parent: Option<Rc<Env>>,
+}
+
+impl Env {
+    /// Create the global scope that has the variables
+    pub fn new(vars: HashMap<String, Type>) -> Rc<Env> {
+        Rc::new(Env {
+            vars: RefCell::new(vars),
+            linked: HashMap::new(),
+            parent: None,
+        })
+    }
+
+    /// Create the empty scope inside the parent
+    pub fn child(parent: &Rc<Env>) -> Rc<Env> {
+        Rc::new(Env {
+            vars: RefCell::new(HashMap::new()),
+            linked: HashMap::new(),
+            parent: Some(parent.clone()),
+        })
+    }
+
+    /// Create the scope inside the parent whose variables are linked to the cells,
+    /// so that defining or removing them is seen by the owner of the cells
+    pub fn linked(parent: &Rc<Env>, linked: HashMap<String, Shared>) -> Rc<Env> {
+        Rc::new(Env {
+            vars: RefCell::new(HashMap::new()),
+            linked,
+            parent: Some(parent.clone()),
+        })
+    }
+
+    /// Look up the variable from this scope to the outer ones
+    pub fn get(&self, name: &str) -> Option<Type> {
+        let mut env = self;
+        loop {
+            if let Some(value) = env.linked.get(name).and_then(|cell| cell.borrow().clone()) {
+                return Some(value);
+            }
+            if let Some(value) = env.vars.borrow().get(name) {
+                return Some(attach(value.clone()));
+            }
+            env = env.parent.as_deref()?;
+        }
+    }
+
+    /// Define the variable in this scope
+    pub fn define(&self, name: impl ToString, value: Type) {
+        let name = name.to_string();
+        match self.linked.get(&name) {
+            Some(cell) => *cell.borrow_mut() = Some(value),
+            None => {
+                let value = self.detach(value);
+                self.vars.borrow_mut().insert(name, value);
+            }
+        }
+    }
+
+    /// Remove the variable that's defined in this scope
+    pub fn remove(&self, name: &str) -> Option<Type> {
+        if let Some(cell) = self.linked.get(name) {
+            return cell.borrow_mut().take();
+        }
+        self.vars.borrow_mut().remove(name).map(attach)
+    }
+
+    /// Make the function defined in this scope capture it weakly before storing it here
+    fn detach(&self, value: Type) -> Type {
+        match value {
+            Type::Function(Function::UserDefined(name, mut clauses)) => {
+                for (_, (_, captured)) in clauses.iter_mut() {
+                    if let Captured::Strong(scope) = captured {
+                        if std::ptr::eq(Rc::as_ptr(scope), self) {
+                            *captured = Captured::Weak(Rc::downgrade(scope));
+                        }
+                    }
+                }
+                Type::Function(Function::UserDefined(name, clauses))
+            }
+            value => value,
+        }
+    }
+
+    /// The global scope that this scope belongs to
+    pub fn root(self: &Rc<Env>) -> Rc<Env> {
+        let mut env = self;
+        while let Some(parent) = &env.parent {
+            env = parent;
+        }
+        env.clone()
+    }
+}
+
+impl fmt::Debug for Env {
+    // Functions in the scope may capture the scope itself, so don't show the contents
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = self.vars.borrow().len() + self.linked.len();
+        write!(f, "Env({count} variables)")
+    }
+}
+
+/// Make the function taken out of the scope capture it strongly,
+/// since the scope may be dropped while the function is still used
+fn attach(value: Type) -> Type {
+    match value {
+        Type::Function(Function::UserDefined(name, mut clauses)) => {
+            for (_, (_, captured)) in clauses.iter_mut() {
+                if let Captured::Weak(_) = captured {
+                    *captured = Captured::Strong(captured.scope());
+                }
+            }
+            Type::Function(Function::UserDefined(name, clauses))
+        }
+        value => value,
+    }
+}
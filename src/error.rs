@@ -0,0 +1,282 @@
+//! Runtime error that's raised while running Pravda program
+use crate::diagnostic::{excerpt, source_line, Diagnostic};
+use crate::lexer::Span;
+use crate::Type;
+use im_rc::HashMap;
+use std::cell::RefCell;
+use std::fmt;
+
+/// Kind of the error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// The source code can't be parsed
+    Syntax,
+    /// Function is called with wrong number of arguments
+    Arity,
+    /// Value of unexpected type is passed
+    Type,
+    /// Value is right type but inappropriate
+    Value,
+    /// No clause of user-defined function matches the arguments
+    Match,
+    /// Operating system failed such as reading file
+    Io,
+    /// Python function raised exception
+    Python,
+    /// Function calling is nested deeper than the limit
+    Recursion,
+    /// Raised by `raise` in the program
+    User,
+}
+
+impl ErrorKind {
+    const ALL: [ErrorKind; 9] = [
+        ErrorKind::Syntax,
+        ErrorKind::Arity,
+        ErrorKind::Type,
+        ErrorKind::Value,
+        ErrorKind::Match,
+        ErrorKind::Io,
+        ErrorKind::Python,
+        ErrorKind::Recursion,
+        ErrorKind::User,
+    ];
+
+    /// Get the kind from its name, e.g. `type`
+    fn from_name(name: &str) -> Option<ErrorKind> {
+        ErrorKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == name)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Syntax => "syntax",
+            ErrorKind::Arity => "arity",
+            ErrorKind::Type => "type",
+            ErrorKind::Value => "value",
+            ErrorKind::Match => "match",
+            ErrorKind::Io => "io",
+            ErrorKind::Python => "python",
+            ErrorKind::Recursion => "recursion",
+            ErrorKind::User => "user",
+        };
+        write!(f, "{name}")
+    }
+}
+
+thread_local! {
+    /// Spans of the locations that the handlers of `try` have got,
+    /// so that the error raised again has the same location
+    static LOCATIONS: RefCell<std::collections::HashMap<String, Span>> = Default::default();
+}
+
+/// Error that stops running the program
+#[derive(Clone, Debug)]
+pub struct PravdaError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Where the error is raised
+    pub span: Option<Span>,
+    /// Frames of function calling that the error passed through, innermost first
+    pub stack: Vec<Frame>,
+    /// Additional fields that the handler of `try` can read
+    pub details: Vec<(String, Type)>,
+}
+
+impl PravdaError {
+    pub fn new(kind: ErrorKind, message: impl ToString) -> PravdaError {
+        PravdaError {
+            kind,
+            message: message.to_string(),
+            span: None,
+            stack: Vec::new(),
+            details: Vec::new(),
+        }
+    }
+
+    /// Attach the additional field
+    pub fn with(mut self, name: &str, value: Type) -> PravdaError {
+        self.details.push((name.to_string(), value));
+        self
+    }
+
+    /// Convert to struct value that's passed to the handler of `try`
+    /// It has fields `kind`, `message`, `location` and the additional ones
+    pub fn to_value(&self) -> Type {
+        let location = match &self.span {
+            Some(span) => {
+                let location = span.to_string();
+                LOCATIONS.with(|locations| {
+                    locations
+                        .borrow_mut()
+                        .insert(location.clone(), span.clone())
+                });
+                Type::String(location)
+            }
+            None => Type::Null,
+        };
+        let mut fields = HashMap::from_iter([
+            ("kind".to_string(), Type::String(self.kind.to_string())),
+            ("message".to_string(), Type::String(self.message.clone())),
+            ("location".to_string(), location),
+        ]);
+        fields.extend(self.details.iter().cloned());
+        Type::Struct(fields)
+    }
+
+    /// Convert back from the struct value that's made by `to_value`,
+    /// so that the error raised again keeps its kind, message and location
+    pub fn from_value(value: &Type) -> Option<PravdaError> {
+        let Type::Struct(fields) = value else {
+            return None;
+        };
+        let kind = match fields.get("kind")? {
+            Type::String(kind) => ErrorKind::from_name(kind)?,
+            _ => return None,
+        };
+        let Type::String(message) = fields.get("message")? else {
+            return None;
+        };
+        let span = match fields.get("location")? {
+            Type::String(location) => {
+                LOCATIONS.with(|locations| locations.borrow().get(location).cloned())
+            }
+            _ => None,
+        };
+        let mut err = PravdaError::new(kind, message);
+        err.span = span;
+        for (name, value) in fields {
+            if !matches!(name.as_str(), "kind" | "message" | "location") {
+                err = err.with(name, value.clone());
+            }
+        }
+        Some(err)
+    }
+
+    /// Record where the error is raised, if it's not known yet
+    pub fn at(mut self, span: &Span) -> PravdaError {
+        if self.span.is_none() {
+            self.span = Some(span.clone());
+        }
+        self
+    }
+
+    /// Record the frame of the function that the error passed through
+    pub fn in_frame(mut self, function: impl ToString, clause: Option<String>) -> PravdaError {
+        self.stack.push(Frame {
+            function: function.to_string(),
+            clause,
+            call_site: None,
+        });
+        self
+    }
+
+    /// Record the location of the calling that the error passed through
+    /// It becomes call site of the outermost frame if it's not known yet,
+    /// or where the error is raised if there's no frame
+    pub fn called_at(mut self, span: &Span) -> PravdaError {
+        match self.stack.last_mut() {
+            Some(frame) if frame.call_site.is_none() => frame.call_site = Some(span.clone()),
+            Some(_) => {}
+            None => return self.at(span),
+        }
+        self
+    }
+}
+
+/// Frame of the function calling
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// Name of the function that's called
+    pub function: String,
+    /// Clause of the function that's chosen by pattern matching, e.g. `fact 0`
+    pub clause: Option<String>,
+    /// Where the function is called
+    pub call_site: Option<Span>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.clause {
+            Some(clause) => write!(f, "{} (clause `{clause}`)", self.function),
+            None => write!(f, "{}", self.function),
+        }
+    }
+}
+
+/// Number of the same frames to show before omitting the rest
+const REPEATED_FRAMES: usize = 3;
+
+impl From<Diagnostic> for PravdaError {
+    fn from(diagnostic: Diagnostic) -> PravdaError {
+        PravdaError::new(ErrorKind::Syntax, diagnostic.message).at(&diagnostic.span)
+    }
+}
+
+impl fmt::Display for PravdaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.stack.is_empty() {
+            writeln!(f, "Traceback (most recent call last):")?;
+
+            // Each call site is in the function of the outer frame
+            let mut entries = Vec::new();
+            let mut current = "<main>".to_string();
+            for frame in self.stack.iter().rev() {
+                entries.push((frame.call_site.clone(), current));
+                current = frame.to_string();
+            }
+
+            let mut repeated = 0;
+            for (index, (call_site, function)) in entries.iter().enumerate() {
+                let same = |(a, f): &(Option<Span>, String), (b, g): &(Option<Span>, String)| {
+                    a.as_ref().map(Span::to_string) == b.as_ref().map(Span::to_string) && f == g
+                };
+                if index > 0 && same(&entries[index - 1], &entries[index]) {
+                    repeated += 1;
+                    if repeated >= REPEATED_FRAMES {
+                        continue;
+                    }
+                } else if repeated >= REPEATED_FRAMES {
+                    writeln!(
+                        f,
+                        "  [previous frame repeated {} more times]",
+                        repeated - REPEATED_FRAMES + 1
+                    )?;
+                    repeated = 0;
+                } else {
+                    repeated = 0;
+                }
+                match call_site {
+                    Some(span) => writeln!(
+                        f,
+                        "  at {span}, in {function}\n    {}",
+                        source_line(span).trim()
+                    )?,
+                    None => writeln!(f, "  in {function}")?,
+                }
+            }
+            if repeated >= REPEATED_FRAMES {
+                writeln!(
+                    f,
+                    "  [previous frame repeated {} more times]",
+                    repeated - REPEATED_FRAMES + 1
+                )?;
+            }
+
+            // Where the error is raised
+            match &self.span {
+                Some(span) => writeln!(f, "  at {span}, in {current}")?,
+                None => writeln!(f, "  in {current}")?,
+            }
+        }
+
+        write!(f, "Error! {} [{}]", self.message, self.kind)?;
+        if let Some(span) = &self.span {
+            write!(f, "\n{}", excerpt(span))?;
+        }
+        Ok(())
+    }
+}
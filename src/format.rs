@@ -0,0 +1,226 @@
+//! Format spec of the string interpolation, e.g. `f"{price:>8.2}"`
+//!
+//! The spec is `[[fill]align][+][0][width][.precision][kind]`
+//! * `align` - `<` left, `>` right or `^` center
+//! * `+` - Show the sign of positive number too
+//! * `0` - Pad the number with zeros after the sign
+//! * `precision` - Digits after the decimal point, or maximum length of the string
+//! * `kind` - `s` string, `f` fixed point, `e` exponent, `%` percentage,
+//!   `x` / `X` hexadecimal, `o` octal or `b` binary
+use crate::error::{ErrorKind, PravdaError};
+use crate::number::{self, float_to_string, Int};
+use crate::Type;
+use std::fmt;
+
+/// Maximum width and precision, so that the formatting doesn't run out of memory
+pub const MAX_WIDTH: usize = 1 << 20;
+
+/// Where the value is placed in the width
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// Parsed format spec
+#[derive(Clone, Debug)]
+pub struct Spec {
+    /// Text of the spec as written
+    text: String,
+    fill: char,
+    align: Option<Align>,
+    sign: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+fn align(c: char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    }
+}
+
+impl Spec {
+    /// Parse the format spec, or return the error message
+    pub fn parse(text: &str) -> Result<Spec, String> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut spec = Spec {
+            text: text.to_string(),
+            fill: ' ',
+            align: None,
+            sign: false,
+            zero: false,
+            width: 0,
+            precision: None,
+            kind: None,
+        };
+        let mut index = 0;
+
+        if let Some(align) = chars.get(1).copied().and_then(align) {
+            spec.fill = chars[0];
+            spec.align = Some(align);
+            index = 2;
+        } else if let Some(align) = chars.first().copied().and_then(align) {
+            spec.align = Some(align);
+            index = 1;
+        }
+        if chars.get(index) == Some(&'+') {
+            spec.sign = true;
+            index += 1;
+        }
+        if chars.get(index) == Some(&'0') {
+            spec.zero = true;
+            index += 1;
+        }
+
+        let digits = |index: &mut usize| {
+            let start = *index;
+            while chars.get(*index).is_some_and(|c| c.is_ascii_digit()) {
+                *index += 1;
+            }
+            // It's kept as the maximum if it's too large, that's the error in applying
+            let text: String = chars[start..*index].iter().collect();
+            (!text.is_empty()).then(|| text.parse().unwrap_or(usize::MAX))
+        };
+        spec.width = digits(&mut index).unwrap_or_default();
+        if chars.get(index) == Some(&'.') {
+            index += 1;
+            spec.precision = digits(&mut index);
+            if spec.precision.is_none() {
+                return Err(format!("precision is missing in the format spec `{text}`"));
+            }
+        }
+        if let Some(kind) = chars.get(index).filter(|c| "sfe%xXob".contains(**c)) {
+            spec.kind = Some(*kind);
+            index += 1;
+        }
+
+        if index < chars.len() {
+            return Err(format!("invalid format spec `{text}`"));
+        }
+        Ok(spec)
+    }
+
+    /// Convert the value to string by the spec
+    pub fn apply(&self, value: &Type) -> Result<String, PravdaError> {
+        if self.width > MAX_WIDTH || self.precision.is_some_and(|i| i > MAX_WIDTH) {
+            return Err(PravdaError::new(
+                ErrorKind::Value,
+                format!(
+                    "the width and precision of format `{}` should be at most {MAX_WIDTH}",
+                    self.text
+                ),
+            ));
+        }
+        let number = || number::float(value);
+        let integer = || match value {
+            Type::Number(number) => Int::from_f64(*number).ok_or_else(|| {
+                PravdaError::new(
+                    ErrorKind::Value,
+                    format!(
+                        "format `{}` requires integer, not {}",
+                        self.text,
+                        float_to_string(*number)
+                    ),
+                )
+            }),
+            other => number::integer(other),
+        };
+
+        let body = match (self.kind, value) {
+            (Some(kind @ ('x' | 'X' | 'o' | 'b')), _) => {
+                let value = integer()?;
+                let magnitude = value.to_bigint().magnitude().clone();
+                let digits = match kind {
+                    'x' => format!("{magnitude:x}"),
+                    'X' => format!("{magnitude:X}"),
+                    'o' => format!("{magnitude:o}"),
+                    _ => format!("{magnitude:b}"),
+                };
+                if value.is_negative() {
+                    format!("-{digits}")
+                } else {
+                    digits
+                }
+            }
+            (Some('e'), _) => match self.precision {
+                Some(precision) => format!("{:.precision$e}", number()?),
+                None => format!("{:e}", number()?),
+            },
+            (Some('%'), _) => {
+                let precision = self.precision.unwrap_or_default();
+                format!("{:.precision$}%", number()? * 100.0)
+            }
+            (None, Type::Integer(value)) if self.precision.is_none() => value.to_string(),
+            (Some('f'), _) | (None, Type::Integer(_) | Type::Number(_)) => match self.precision {
+                Some(precision) => format!("{:.precision$}", number()?),
+                None => float_to_string(number()?),
+            },
+            (_, other) => {
+                let text = other.get_string();
+                match self.precision {
+                    Some(precision) => text.chars().take(precision).collect(),
+                    None => text,
+                }
+            }
+        };
+
+        let numeric = !matches!(self.kind, None | Some('s'))
+            || matches!(
+                value,
+                Type::Integer(_) | Type::Rational(_) | Type::Number(_) | Type::Complex(_)
+            );
+        let body = if numeric && self.sign && !body.starts_with('-') {
+            format!("+{body}")
+        } else {
+            body
+        };
+        Ok(self.pad(body, numeric))
+    }
+
+    /// Fill the string to the width
+    fn pad(&self, body: String, numeric: bool) -> String {
+        let length = body.chars().count();
+        if length >= self.width {
+            return body;
+        }
+        let space = self.width - length;
+
+        if numeric && self.zero && self.align.is_none() {
+            // Zeros are put after the sign
+            let (sign, digits) = match body.strip_prefix(['+', '-']) {
+                Some(digits) => body.split_at(body.len() - digits.len()),
+                None => ("", body.as_str()),
+            };
+            return format!("{sign}{}{digits}", "0".repeat(space));
+        }
+
+        let fill = |count: usize| self.fill.to_string().repeat(count);
+        let default = if numeric { Align::Right } else { Align::Left };
+        match self.align.unwrap_or(default) {
+            Align::Left => format!("{body}{}", fill(space)),
+            Align::Right => format!("{}{body}", fill(space)),
+            Align::Center => format!("{}{body}{}", fill(space / 2), fill(space - space / 2)),
+        }
+    }
+}
+
+/// Convert the embedded value to string, by the spec if it's given
+pub fn format_value(value: &Type, spec: Option<&Spec>) -> Result<String, PravdaError> {
+    match spec {
+        Some(spec) => spec.apply(value),
+        None => Ok(value.get_string()),
+    }
+}
+
+impl fmt::Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
@@ -0,0 +1,228 @@
+//! Built-in functions of the file system
+//!
+//! If the operating system fails, the error of kind `io` is raised,
+//! and it has the fields `path` and `reason` such as `"not-found"` for the handler of `try`,
+//! and also `to` if it's the operation between two paths like `rename`
+use crate::error::{ErrorKind, PravdaError};
+use crate::number::Int;
+use crate::string::string;
+use crate::{check_arity, Function, Type};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::time::UNIX_EPOCH;
+
+/// Reason of the error in kebab case, e.g. `NotFound` to `not-found`
+fn reason(err: &io::Error) -> String {
+    let mut reason = String::new();
+    for (index, c) in format!("{:?}", err.kind()).chars().enumerate() {
+        if c.is_uppercase() && index > 0 {
+            reason.push('-');
+        }
+        reason.push(c.to_ascii_lowercase());
+    }
+    reason
+}
+
+/// Make the error of the operation to the path
+fn io_error(action: &str, path: &str, err: io::Error) -> PravdaError {
+    PravdaError::new(ErrorKind::Io, format!("can't {action} \"{path}\": {err}"))
+        .with("path", Type::String(path.to_string()))
+        .with("reason", Type::String(reason(&err)))
+}
+
+/// Make the error of the operation from the path to another one,
+/// since it can't be known which of them is wrong
+fn io_error_to(action: &str, from: &str, to: &str, err: io::Error) -> PravdaError {
+    PravdaError::new(
+        ErrorKind::Io,
+        format!("can't {action} \"{from}\" to \"{to}\": {err}"),
+    )
+    .with("path", Type::String(from.to_string()))
+    .with("to", Type::String(to.to_string()))
+    .with("reason", Type::String(reason(&err)))
+}
+
+/// Read the file as the text
+fn read(path: &str) -> Result<String, PravdaError> {
+    fs::read_to_string(path).map_err(|err| io_error("read", path, err))
+}
+
+pub fn builtins() -> Vec<(String, Type)> {
+    vec![
+        (
+            "read-file".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("read-file", &params, 1)?;
+                Ok(Type::String(read(string(&params[0])?)?))
+            })),
+        ),
+        (
+            "read-lines".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("read-lines", &params, 1)?;
+                Ok(Type::List(
+                    read(string(&params[0])?)?
+                        .lines()
+                        .map(|i| Type::String(i.to_string()))
+                        .collect(),
+                ))
+            })),
+        ),
+        (
+            "read-bytes".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("read-bytes", &params, 1)?;
+                let path = string(&params[0])?;
+                let bytes = fs::read(path).map_err(|err| io_error("read", path, err))?;
+                Ok(Type::List(
+                    bytes
+                        .into_iter()
+                        .map(|i| Type::Integer(Int::Small(i as i64)))
+                        .collect(),
+                ))
+            })),
+        ),
+        (
+            "write-file".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("write-file", &params, 2)?;
+                let path = string(&params[0])?;
+                fs::write(path, params[1].get_string())
+                    .map_err(|err| io_error("write", path, err))?;
+                Ok(Type::Null)
+            })),
+        ),
+        (
+            "append-file".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("append-file", &params, 2)?;
+                let path = string(&params[0])?;
+                OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(path)
+                    .and_then(|mut file| file.write_all(params[1].get_string().as_bytes()))
+                    .map_err(|err| io_error("append to", path, err))?;
+                Ok(Type::Null)
+            })),
+        ),
+        (
+            "file-exists".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("file-exists", &params, 1)?;
+                let path = string(&params[0])?;
+                let exists = fs::exists(path).map_err(|err| io_error("access", path, err))?;
+                Ok(Type::Bool(exists))
+            })),
+        ),
+        (
+            "is-dir".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("is-dir", &params, 1)?;
+                Ok(Type::Bool(
+                    fs::metadata(string(&params[0])?).is_ok_and(|i| i.is_dir()),
+                ))
+            })),
+        ),
+        (
+            "list-dir".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("list-dir", &params, 1)?;
+                let path = string(&params[0])?;
+                // The names of the entries are sorted, since the order of OS isn't specified
+                let mut names = Vec::new();
+                for entry in fs::read_dir(path).map_err(|err| io_error("list", path, err))? {
+                    let entry = entry.map_err(|err| io_error("list", path, err))?;
+                    names.push(entry.file_name().to_string_lossy().to_string());
+                }
+                names.sort();
+                Ok(Type::List(names.into_iter().map(Type::String).collect()))
+            })),
+        ),
+        (
+            "make-dir".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("make-dir", &params, 1)?;
+                // The parent directories are also made, and it's fine if it already exists
+                let path = string(&params[0])?;
+                fs::create_dir_all(path).map_err(|err| io_error("make directory", path, err))?;
+                Ok(Type::Null)
+            })),
+        ),
+        (
+            "remove-file".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("remove-file", &params, 1)?;
+                let path = string(&params[0])?;
+                fs::remove_file(path).map_err(|err| io_error("remove", path, err))?;
+                Ok(Type::Null)
+            })),
+        ),
+        (
+            "rename".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("rename", &params, 2)?;
+                let (from, to) = (string(&params[0])?, string(&params[1])?);
+                fs::rename(from, to).map_err(|err| io_error_to("rename", from, to, err))?;
+                Ok(Type::Null)
+            })),
+        ),
+        (
+            "copy-file".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("copy-file", &params, 2)?;
+                let (from, to) = (string(&params[0])?, string(&params[1])?);
+                fs::copy(from, to).map_err(|err| io_error_to("copy", from, to, err))?;
+                Ok(Type::Null)
+            })),
+        ),
+        (
+            "file-size".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("file-size", &params, 1)?;
+                let path = string(&params[0])?;
+                let metadata = fs::metadata(path).map_err(|err| io_error("access", path, err))?;
+                Ok(Type::Integer(Int::from(metadata.len() as usize)))
+            })),
+        ),
+        (
+            "modified-time".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("modified-time", &params, 1)?;
+                // It's the seconds from the UNIX epoch
+                let path = string(&params[0])?;
+                let time = fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .map_err(|err| io_error("access", path, err))?;
+                Ok(Type::Number(match time.duration_since(UNIX_EPOCH) {
+                    Ok(duration) => duration.as_secs_f64(),
+                    Err(err) => -err.duration().as_secs_f64(),
+                }))
+            })),
+        ),
+        (
+            "glob".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("glob", &params, 1)?;
+                let pattern = string(&params[0])?;
+                let paths = glob::glob(pattern).map_err(|err| {
+                    PravdaError::new(
+                        ErrorKind::Value,
+                        format!("invalid glob pattern \"{pattern}\": {err}"),
+                    )
+                })?;
+                let mut result = Vec::new();
+                for path in paths {
+                    match path {
+                        Ok(path) => result.push(Type::String(path.to_string_lossy().to_string())),
+                        Err(err) => {
+                            let path = err.path().to_string_lossy().to_string();
+                            return Err(io_error("access", &path, err.into()));
+                        }
+                    }
+                }
+                Ok(Type::List(result.into()))
+            })),
+        ),
+    ]
+}
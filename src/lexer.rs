@@ -0,0 +1,587 @@
+//! Lexer that splits Pravda source code into tokens
+use crate::diagnostic::Diagnostic;
+use std::fmt;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::CharIndices;
+
+/// Source code and where it came from
+pub struct Source {
+    /// File path or name of the input such as `<repl>`
+    pub name: String,
+    /// Whole text of the source code
+    pub code: String,
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Source({})", self.name)
+    }
+}
+
+/// Location of the token or node in the source code
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub source: Rc<Source>,
+    /// Byte offset where it starts
+    pub start: usize,
+    /// Byte offset where it ends
+    pub end: usize,
+    /// Line number counted from 1
+    pub line: usize,
+    /// Column number counted from 1 in characters
+    pub column: usize,
+}
+
+impl Span {
+    /// Span that covers from this to the other
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source.name, self.line, self.column)
+    }
+}
+
+/// Token of Pravda source code
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Kind of the token
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    /// `(`
+    LeftParen,
+    /// `)`
+    RightParen,
+    /// `[`
+    LeftBracket,
+    /// `]`
+    RightBracket,
+    /// `{`
+    LeftBrace,
+    /// `}`
+    RightBrace,
+    /// Beginning of lambda expression, `lambda(` or `\(`
+    Lambda,
+    /// Beginning of struct literal, `struct{`
+    Struct,
+    /// Beginning of dictionary literal, `dict{`
+    Dict,
+    /// Beginning of set literal, `set{`
+    Set,
+    /// `=` that separates definition and its value
+    Equal,
+    /// `;` that separates lines of the program
+    Semicolon,
+    /// `->` that separates arguments and body of lambda
+    Arrow,
+    /// Prefix of lazy evaluating, `@` or `lazy`
+    Lazy,
+    /// Prefix of mutable length argument, `~`
+    Spread,
+    /// String literal surrounded by double quote
+    String(String),
+    /// Other word such as number, bool, null and symbol
+    Word(String),
+    /// Interpolated string literal, e.g. `f"total: {(+ a b)}"`
+    Format(Vec<Fragment>),
+    /// Line comment `-- ...` or block comment `{- ... -}` as written
+    /// The parser skips it, but it's kept for tools that rewrite the source code
+    Comment(String),
+}
+
+/// Part of the interpolated string literal
+#[derive(Clone, Debug)]
+pub enum Fragment {
+    /// Text that's written as it is
+    Text(String),
+    /// Embedded code and its format spec, e.g. `{price:>8.2}`
+    Code {
+        tokens: Vec<Token>,
+        spec: Option<String>,
+        span: Span,
+    },
+}
+
+impl PartialEq for Fragment {
+    fn eq(&self, other: &Fragment) -> bool {
+        match (self, other) {
+            (Fragment::Text(a), Fragment::Text(b)) => a == b,
+            (
+                Fragment::Code {
+                    tokens: a, spec: s, ..
+                },
+                Fragment::Code {
+                    tokens: b, spec: t, ..
+                },
+            ) => s == t && a.iter().map(|i| &i.kind).eq(b.iter().map(|i| &i.kind)),
+            _ => false,
+        }
+    }
+}
+
+/// Write the string as literal that has escape sequences
+pub fn escape(string: &str) -> String {
+    let mut literal = String::new();
+    for c in string.chars() {
+        match c {
+            '\n' => literal.push_str("\\n"),
+            '\t' => literal.push_str("\\t"),
+            '\r' => literal.push_str("\\r"),
+            '\0' => literal.push_str("\\0"),
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            c if c.is_control() => literal.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal
+}
+
+/// Is the character separates words?
+fn is_delimiter(c: char) -> bool {
+    is_space(c) || "()[]{};=\"".contains(c)
+}
+
+/// Is the character white space?
+fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\n' | '\t' | '\r' | '　')
+}
+
+/// Whether `{-` followed by the character opens the block comment
+/// It should be the space or `-` like `{--`, so that `{-1}` is the block of negative number
+fn opens_comment(c: Option<char>) -> bool {
+    c.is_none_or(|c| is_space(c) || c == '-')
+}
+
+struct Lexer<'a> {
+    source: Rc<Source>,
+    chars: Peekable<CharIndices<'a>>,
+    /// Byte offset where the code to tokenize ends
+    end: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Lexer<'_> {
+    fn next(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    /// Character after the next one
+    fn peek_second(&mut self) -> Option<char> {
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next().map(|(_, c)| c)
+    }
+
+    fn offset(&mut self) -> usize {
+        match self.chars.peek() {
+            Some((index, _)) => *index,
+            None => self.end,
+        }
+    }
+
+    /// Empty span at the current position
+    fn here(&mut self) -> Span {
+        let start = self.offset();
+        Span {
+            source: self.source.clone(),
+            start,
+            end: start,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Source code after the current position
+    fn rest(&mut self) -> &str {
+        let start = self.offset();
+        &self.source.code[start..self.end]
+    }
+
+    /// Number of `#` after `r` if it's beginning of raw string
+    fn raw_hashes(&mut self) -> Option<usize> {
+        let rest = self.rest();
+        let hashes = rest.chars().take_while(|c| *c == '#').count();
+        rest[hashes..].starts_with('"').then_some(hashes)
+    }
+
+    /// Read the string literal until the closing quote
+    /// # Arguments
+    /// * `start` - Where the string literal starts
+    /// * `close` - Closing quote of the literal
+    /// * `escape` - Whether to process escape sequences, it's false for raw string
+    fn string(&mut self, start: &Span, close: &str, escape: bool) -> Result<String, Diagnostic> {
+        let mut string = String::new();
+        loop {
+            if self.rest().starts_with(close) {
+                for _ in close.chars() {
+                    self.next();
+                }
+                return Ok(string);
+            }
+            let at = self.here();
+            match self.next() {
+                Some('\\') if escape => string.push(self.escape(&at)?),
+                Some(c) => string.push(c),
+                None => {
+                    return Err(Diagnostic::new(
+                        "the string literal is not closed",
+                        start.to(&self.here()),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Read the interpolated string literal until the closing quote
+    fn format(&mut self, start: &Span, close: &str) -> Result<Vec<Fragment>, Diagnostic> {
+        let mut fragments = Vec::new();
+        let mut text = String::new();
+        loop {
+            if self.rest().starts_with(close) {
+                for _ in close.chars() {
+                    self.next();
+                }
+                break;
+            }
+            // Doubled braces are the literal ones
+            if self.rest().starts_with("{{") || self.rest().starts_with("}}") {
+                text.extend(self.next());
+                self.next();
+                continue;
+            }
+            let at = self.here();
+            match self.next() {
+                Some('{') => {
+                    if !text.is_empty() {
+                        fragments.push(Fragment::Text(std::mem::take(&mut text)));
+                    }
+                    fragments.push(self.interpolation(&at)?);
+                }
+                Some('}') => {
+                    return Err(Diagnostic::new(
+                        "`}` in the interpolated string should be doubled as `}}`",
+                        self.span_from(&at),
+                    ))
+                }
+                Some('\\') => text.push(self.escape(&at)?),
+                Some(c) => text.push(c),
+                None => {
+                    return Err(Diagnostic::new(
+                        "the string literal is not closed",
+                        start.to(&self.here()),
+                    ))
+                }
+            }
+        }
+        if !text.is_empty() {
+            fragments.push(Fragment::Text(text));
+        }
+        Ok(fragments)
+    }
+
+    /// Read the embedded code after `{`, and its format spec after `:`
+    fn interpolation(&mut self, start: &Span) -> Result<Fragment, Diagnostic> {
+        // Find the closing brace, except ones in the brackets and strings
+        let (mut depth, mut quote, mut escaped) = (0, false, false);
+        let (mut colon, mut close) = (None, None);
+        for (index, c) in self.rest().char_indices() {
+            if quote {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => quote = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '"' => quote = true,
+                '(' | '[' | '{' => depth += 1,
+                '}' if depth == 0 => {
+                    close = Some(index);
+                    break;
+                }
+                ')' | ']' | '}' => depth -= 1,
+                ':' if depth == 0 && colon.is_none() => colon = Some(index),
+                _ => {}
+            }
+        }
+        let Some(close) = close else {
+            return Err(Diagnostic::new(
+                "the interpolation is not closed by `}`",
+                start.to(&self.here()),
+            ));
+        };
+
+        // Tokenize the embedded code in the place of the source code
+        let offset = self.offset();
+        let end = offset + colon.unwrap_or(close);
+        let source = self.source.clone();
+        let mut chars = source.code[..end].char_indices().peekable();
+        while chars.next_if(|(index, _)| *index < offset).is_some() {}
+        let mut lexer = Lexer {
+            source: source.clone(),
+            chars,
+            end,
+            line: self.line,
+            column: self.column,
+        };
+        let tokens = scan(&mut lexer)?;
+
+        while self.offset() < end {
+            self.next();
+        }
+        let spec = colon.map(|colon| {
+            self.next();
+            while self.offset() < offset + close {
+                self.next();
+            }
+            source.code[offset + colon + 1..offset + close].to_string()
+        });
+        self.next();
+        Ok(Fragment::Code {
+            tokens,
+            spec,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Read the escape sequence after the backslash
+    fn escape(&mut self, start: &Span) -> Result<char, Diagnostic> {
+        let c = match self.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('u') if self.peek() == Some('{') => {
+                // Unicode code point in hexadecimal, e.g. `\u{1F600}`
+                self.next();
+                let mut digits = String::new();
+                while let Some(c) = self.peek().filter(|c| *c != '}' && *c != '"') {
+                    digits.push(c);
+                    self.next();
+                }
+                let closed = self.peek() == Some('}');
+                if closed {
+                    self.next();
+                }
+                match u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                {
+                    Some(c) if closed && digits.len() <= 6 => c,
+                    _ => {
+                        return Err(Diagnostic::new(
+                            format!("invalid unicode escape `{}`", self.text_from(start)),
+                            self.span_from(start),
+                        ))
+                    }
+                }
+            }
+            Some(c) => {
+                return Err(Diagnostic::new(
+                    format!("unknown escape sequence `\\{c}`"),
+                    self.span_from(start),
+                ))
+            }
+            None => {
+                return Err(Diagnostic::new(
+                    "the string literal is not closed",
+                    self.span_from(start),
+                ))
+            }
+        };
+        Ok(c)
+    }
+
+    /// Source code from the start to the current position
+    fn text_from(&mut self, start: &Span) -> &str {
+        let end = self.offset();
+        &self.source.code[start.start..end]
+    }
+
+    /// Span from the start to the current position
+    fn span_from(&mut self, start: &Span) -> Span {
+        Span {
+            end: self.offset(),
+            ..start.clone()
+        }
+    }
+}
+
+/// Tokenize the source code
+/// The comments are kept as the tokens, and the parser skips them
+pub fn tokenize(source: Rc<Source>) -> Result<Vec<Token>, Diagnostic> {
+    let code = source.code.clone();
+    let mut lexer = Lexer {
+        source,
+        chars: code.char_indices().peekable(),
+        end: code.len(),
+        line: 1,
+        column: 1,
+    };
+    scan(&mut lexer)
+}
+
+/// Split the rest of the code that the lexer has into tokens
+fn scan(lexer: &mut Lexer) -> Result<Vec<Token>, Diagnostic> {
+    let mut tokens = Vec::new();
+    loop {
+        let start = lexer.here();
+        let Some(c) = lexer.next() else {
+            break;
+        };
+        let kind = match c {
+            c if is_space(c) => continue,
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '[' => TokenKind::LeftBracket,
+            ']' => TokenKind::RightBracket,
+            '{' if lexer.peek() == Some('-') && opens_comment(lexer.peek_second()) => {
+                // Block comment can be nested
+                lexer.next();
+                let mut depth = 1;
+                while depth > 0 {
+                    match (lexer.next(), lexer.peek()) {
+                        (Some('{'), Some('-')) if opens_comment(lexer.peek_second()) => {
+                            lexer.next();
+                            depth += 1;
+                        }
+                        (Some('-'), Some('}')) => {
+                            lexer.next();
+                            depth -= 1;
+                        }
+                        (Some(_), _) => {}
+                        (None, _) => {
+                            return Err(Diagnostic::new(
+                                "the block comment is not closed",
+                                start.to(&lexer.here()),
+                            ))
+                        }
+                    }
+                }
+                TokenKind::Comment(lexer.text_from(&start).to_string())
+            }
+            '{' => TokenKind::LeftBrace,
+            '}' => TokenKind::RightBrace,
+            ';' => TokenKind::Semicolon,
+            '=' => TokenKind::Equal,
+            '@' => TokenKind::Lazy,
+            '~' => TokenKind::Spread,
+            '\\' if lexer.peek() == Some('(') => {
+                lexer.next();
+                TokenKind::Lambda
+            }
+            '-' if lexer.peek() == Some('>') => {
+                lexer.next();
+                TokenKind::Arrow
+            }
+            '-' if lexer.peek() == Some('-') => {
+                // Line comment continues to the end of the line
+                while lexer.peek().is_some_and(|c| c != '\n') {
+                    lexer.next();
+                }
+                TokenKind::Comment(lexer.text_from(&start).trim_end_matches('\r').to_string())
+            }
+            'f' if lexer.peek() == Some('"') => {
+                // Interpolated string, e.g. `f"total: {(+ a b)}"`
+                let close = if lexer.rest().starts_with("\"\"\"") {
+                    "\"\"\""
+                } else {
+                    "\""
+                };
+                for _ in close.chars() {
+                    lexer.next();
+                }
+                TokenKind::Format(lexer.format(&start, close)?)
+            }
+            '"' if lexer.rest().starts_with("\"\"") => {
+                // Triple-quoted string can be written over several lines
+                lexer.next();
+                lexer.next();
+                if lexer.rest().starts_with("\r\n") {
+                    lexer.next();
+                }
+                if lexer.peek() == Some('\n') {
+                    lexer.next();
+                }
+                TokenKind::String(lexer.string(&start, "\"\"\"", true)?)
+            }
+            '"' => TokenKind::String(lexer.string(&start, "\"", true)?),
+            'r' if lexer.raw_hashes().is_some() => {
+                // Raw string, e.g. `r"C:\path"` or `r#"say "hi""#`
+                let hashes = lexer.raw_hashes().unwrap_or_default();
+                for _ in 0..=hashes {
+                    lexer.next();
+                }
+                let close = format!("\"{}", "#".repeat(hashes));
+                TokenKind::String(lexer.string(&start, &close, false)?)
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = lexer.peek() {
+                    if is_delimiter(c)
+                        || (c == '-' && matches!(lexer.peek_second(), Some('>' | '-')))
+                    {
+                        break;
+                    }
+                    word.push(c);
+                    lexer.next();
+                }
+
+                // Words that are followed by the bracket are the prefix of the special form
+                match (word.as_str(), lexer.peek()) {
+                    ("lambda", Some('(')) => {
+                        lexer.next();
+                        TokenKind::Lambda
+                    }
+                    ("struct", Some('{')) => {
+                        lexer.next();
+                        TokenKind::Struct
+                    }
+                    ("dict", Some('{')) => {
+                        lexer.next();
+                        TokenKind::Dict
+                    }
+                    ("set", Some('{')) => {
+                        lexer.next();
+                        TokenKind::Set
+                    }
+                    ("lazy", Some('(' | '{')) => TokenKind::Lazy,
+                    _ => TokenKind::Word(word),
+                }
+            }
+        };
+        tokens.push(Token {
+            kind,
+            span: lexer.span_from(&start),
+        });
+    }
+    Ok(tokens)
+}
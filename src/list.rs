@@ -0,0 +1,630 @@
+//! Built-in functions to process the lists
+use crate::dict::{Dict, Key};
+use crate::env::Env;
+use crate::error::{ErrorKind, PravdaError};
+use crate::number::{self, Int};
+use crate::string;
+use crate::{call_function, check_arity, type_error, Function, Type};
+use im_rc::{vector, Vector};
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+/// Get the list from the value, or error if it's other type
+fn list(value: &Type) -> Result<&Vector<Type>, PravdaError> {
+    match value {
+        Type::List(list) => Ok(list),
+        other => Err(type_error("list", other)),
+    }
+}
+
+/// Get the count of the elements, that should be non-negative integer
+fn count(value: &Type) -> Result<usize, PravdaError> {
+    number::integer(value)?
+        .to_i64()
+        .and_then(|count| usize::try_from(count).ok())
+        .ok_or_else(|| {
+            PravdaError::new(
+                ErrorKind::Value,
+                format!(
+                    "the count should be non-negative integer, not {}",
+                    value.get_symbol()
+                ),
+            )
+        })
+}
+
+/// Get the bound of the slice, that's clamped into the length
+/// Negative index counts from the end
+fn bound(value: &Type, length: usize) -> Result<usize, PravdaError> {
+    let index = number::integer(value)?;
+    Ok(match index.to_i64() {
+        Some(i) if i < 0 => length.saturating_sub(i.unsigned_abs() as usize),
+        Some(i) => (i as u64).min(length as u64) as usize,
+        None if index < Int::Small(0) => 0,
+        None => length,
+    })
+}
+
+/// Call the predicate with the element
+fn test(function: &Function, item: &Type, memory: &Rc<Env>) -> Result<bool, PravdaError> {
+    Ok(call_function(function.clone(), vec![item.clone()], memory)?.get_bool())
+}
+
+/// Sort the values stably by the merge sort
+/// The comparator can fail or be inconsistent, so the sort of standard library isn't used
+fn merge_sort(
+    items: Vec<Type>,
+    less: &mut dyn FnMut(&Type, &Type) -> Result<bool, PravdaError>,
+) -> Result<Vec<Type>, PravdaError> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let mut left = items;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(left, less)?;
+    let right = merge_sort(right, less)?;
+
+    let mut result = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // The left one comes first unless the right one is less, so that it's stable
+        if less(b, a)? {
+            result.push(right.next().unwrap());
+        } else {
+            result.push(left.next().unwrap());
+        }
+    }
+    result.extend(left);
+    result.extend(right);
+    Ok(result)
+}
+
+/// Find the least or greatest value of the arguments, or the list if it's only one
+fn extreme(name: &str, params: &[Type], expected: Ordering) -> Result<Type, PravdaError> {
+    check_arity(name, params, 1)?;
+    let items = match params {
+        [Type::List(items)] => items.clone(),
+        _ => params.iter().cloned().collect(),
+    };
+    let mut result: Option<Type> = None;
+    for item in items {
+        let better = match &result {
+            None => true,
+            Some(current) if number::is_number(&item) && number::is_number(current) => {
+                number::compare(&item, current)? == Some(expected)
+            }
+            Some(current) => item.cmp(current) == expected,
+        };
+        if better {
+            result = Some(item);
+        }
+    }
+    result.ok_or_else(|| PravdaError::new(ErrorKind::Value, format!("`{name}` of the empty list")))
+}
+
+pub fn builtins() -> Vec<(String, Type)> {
+    vec![
+        (
+            "sort".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("sort", &params, 1)?;
+                let mut items: Vec<Type> = list(&params[0])?.iter().cloned().collect();
+                items.sort();
+                Ok(Type::List(items.into()))
+            })),
+        ),
+        (
+            "sort-by".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("sort-by", &params, 2)?;
+                let items: Vec<Type> = list(&params[0])?.iter().cloned().collect();
+                let function = params[1].get_function()?;
+
+                // The key function is called once for each element
+                let mut keyed = Vec::with_capacity(items.len());
+                for item in items {
+                    keyed.push((
+                        call_function(function.clone(), vec![item.clone()], memory)?,
+                        item,
+                    ));
+                }
+                keyed.sort_by(|a, b| a.0.cmp(&b.0));
+                Ok(Type::List(
+                    keyed.into_iter().map(|(_, item)| item).collect(),
+                ))
+            })),
+        ),
+        (
+            "sort-with".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("sort-with", &params, 2)?;
+                let items: Vec<Type> = list(&params[0])?.iter().cloned().collect();
+                let function = params[1].get_function()?;
+
+                // The comparator returns the number whose sign is the order, or whether it's less
+                let mut less = |a: &Type, b: &Type| {
+                    let params = vec![a.clone(), b.clone()];
+                    match call_function(function.clone(), params, memory)? {
+                        Type::Bool(value) => Ok(value),
+                        other => Ok(number::compare(&other, &Type::Integer(Int::Small(0)))?
+                            == Some(Ordering::Less)),
+                    }
+                };
+                Ok(Type::List(merge_sort(items, &mut less)?.into()))
+            })),
+        ),
+        (
+            "reverse".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("reverse", &params, 1)?;
+                match &params[0] {
+                    Type::String(text) => Ok(Type::String(text.chars().rev().collect())),
+                    other => Ok(Type::List(list(other)?.iter().rev().cloned().collect())),
+                }
+            })),
+        ),
+        (
+            "zip".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("zip", &params, 1)?;
+                let lists = params.iter().map(list).collect::<Result<Vec<_>, _>>()?;
+                let length = lists.iter().map(|i| i.len()).min().unwrap_or(0);
+                Ok(Type::List(
+                    (0..length)
+                        .map(|index| Type::List(lists.iter().map(|i| i[index].clone()).collect()))
+                        .collect(),
+                ))
+            })),
+        ),
+        (
+            "unzip".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("unzip", &params, 1)?;
+                let rows = list(&params[0])?
+                    .iter()
+                    .map(list)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let length = rows.iter().map(|i| i.len()).min().unwrap_or(0);
+                Ok(Type::List(
+                    (0..length)
+                        .map(|index| Type::List(rows.iter().map(|i| i[index].clone()).collect()))
+                        .collect(),
+                ))
+            })),
+        ),
+        (
+            "enumerate".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("enumerate", &params, 1)?;
+                let start = match params.get(1) {
+                    Some(start) => number::integer(start)?,
+                    None => Int::Small(0),
+                };
+                Ok(Type::List(
+                    list(&params[0])?
+                        .iter()
+                        .enumerate()
+                        .map(|(index, item)| {
+                            let index = &start + &Int::from(index);
+                            Type::List(vector![Type::Integer(index), item.clone()])
+                        })
+                        .collect(),
+                ))
+            })),
+        ),
+        (
+            "take".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("take", &params, 2)?;
+                let items = list(&params[0])?;
+                Ok(Type::List(items.take(count(&params[1])?.min(items.len()))))
+            })),
+        ),
+        (
+            "drop".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("drop", &params, 2)?;
+                let items = list(&params[0])?;
+                Ok(Type::List(items.skip(count(&params[1])?.min(items.len()))))
+            })),
+        ),
+        (
+            "slice".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("slice", &params, 2)?;
+                let length = match &params[0] {
+                    Type::String(text) => text.chars().count(),
+                    other => list(other)?.len(),
+                };
+                let start = bound(&params[1], length)?;
+                let end = match params.get(2) {
+                    Some(end) => bound(end, length)?.max(start),
+                    None => length,
+                };
+                match &params[0] {
+                    Type::String(text) => Ok(Type::String(
+                        text.chars().skip(start).take(end - start).collect(),
+                    )),
+                    other => Ok(Type::List(list(other)?.clone().slice(start..end))),
+                }
+            })),
+        ),
+        (
+            "take-while".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("take-while", &params, 2)?;
+                let items = list(&params[0])?;
+                let function = params[1].get_function()?;
+                let mut length = 0;
+                while length < items.len() && test(&function, &items[length], memory)? {
+                    length += 1;
+                }
+                Ok(Type::List(items.take(length)))
+            })),
+        ),
+        (
+            "drop-while".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("drop-while", &params, 2)?;
+                let items = list(&params[0])?;
+                let function = params[1].get_function()?;
+                let mut length = 0;
+                while length < items.len() && test(&function, &items[length], memory)? {
+                    length += 1;
+                }
+                Ok(Type::List(items.skip(length)))
+            })),
+        ),
+        (
+            "nth".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("nth", &params, 2)?;
+                let items = list(&params[0])?;
+                // Negative index counts from the end
+                let index = number::integer(&params[1])?;
+                let position = match index.to_i64() {
+                    Some(i) if i < 0 => i.checked_add(items.len() as i64),
+                    Some(i) => Some(i),
+                    None => None,
+                };
+                match position.and_then(|i| usize::try_from(i).ok()) {
+                    Some(i) if i < items.len() => Ok(items[i].clone()),
+                    _ => Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!(
+                            "the index {index} is out of range for the list of length {}",
+                            items.len()
+                        ),
+                    )),
+                }
+            })),
+        ),
+        (
+            "last".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("last", &params, 1)?;
+                Ok(list(&params[0])?.back().cloned().unwrap_or(Type::Null))
+            })),
+        ),
+        (
+            "find".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("find", &params, 2)?;
+                let function = params[1].get_function()?;
+                for item in list(&params[0])? {
+                    if test(&function, item, memory)? {
+                        return Ok(item.clone());
+                    }
+                }
+                Ok(Type::Null)
+            })),
+        ),
+        (
+            "index-of".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("index-of", &params, 2)?;
+                let index = match &params[0] {
+                    Type::String(text) => string::index_of(text, string::string(&params[1])?),
+                    other => list(other)?.index_of(&params[1]),
+                };
+                match index {
+                    Some(index) => Ok(Type::Integer(Int::from(index))),
+                    None => Ok(Type::Null),
+                }
+            })),
+        ),
+        (
+            "any".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("any", &params, 1)?;
+                for item in list(&params[0])? {
+                    let result = match params.get(1) {
+                        Some(function) => test(&function.get_function()?, item, memory)?,
+                        None => item.get_bool(),
+                    };
+                    if result {
+                        return Ok(Type::Bool(true));
+                    }
+                }
+                Ok(Type::Bool(false))
+            })),
+        ),
+        (
+            "all".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("all", &params, 1)?;
+                for item in list(&params[0])? {
+                    let result = match params.get(1) {
+                        Some(function) => test(&function.get_function()?, item, memory)?,
+                        None => item.get_bool(),
+                    };
+                    if !result {
+                        return Ok(Type::Bool(false));
+                    }
+                }
+                Ok(Type::Bool(true))
+            })),
+        ),
+        (
+            "flatten".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("flatten", &params, 1)?;
+                // The nested lists are flattened fully, unless the depth is given
+                fn flatten(items: &Vector<Type>, depth: Option<usize>, result: &mut Vector<Type>) {
+                    for item in items {
+                        match item {
+                            Type::List(inner) if depth != Some(0) => {
+                                flatten(inner, depth.map(|i| i - 1), result)
+                            }
+                            other => result.push_back(other.clone()),
+                        }
+                    }
+                }
+                let depth = params.get(1).map(count).transpose()?;
+                let mut result = Vector::new();
+                flatten(list(&params[0])?, depth, &mut result);
+                Ok(Type::List(result))
+            })),
+        ),
+        (
+            "flat-map".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("flat-map", &params, 2)?;
+                let function = params[1].get_function()?;
+                let mut result = Vector::new();
+                for item in list(&params[0])? {
+                    match call_function(function.clone(), vec![item.clone()], memory)? {
+                        Type::List(items) => result.append(items),
+                        other => result.push_back(other),
+                    }
+                }
+                Ok(Type::List(result))
+            })),
+        ),
+        (
+            "group-by".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("group-by", &params, 2)?;
+                let function = params[1].get_function()?;
+                let mut groups = Dict::new();
+                for item in list(&params[0])? {
+                    let key = call_function(function.clone(), vec![item.clone()], memory)?;
+                    let group = groups
+                        .entry(Key::new(&key)?)
+                        .or_insert_with(|| Type::List(Vector::new()));
+                    if let Type::List(group) = group {
+                        group.push_back(item.clone());
+                    }
+                }
+                Ok(Type::Dict(groups))
+            })),
+        ),
+        (
+            "partition".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("partition", &params, 2)?;
+                let function = params[1].get_function()?;
+                let (mut matched, mut rest) = (Vector::new(), Vector::new());
+                for item in list(&params[0])? {
+                    if test(&function, item, memory)? {
+                        matched.push_back(item.clone());
+                    } else {
+                        rest.push_back(item.clone());
+                    }
+                }
+                Ok(Type::List(vector![Type::List(matched), Type::List(rest)]))
+            })),
+        ),
+        (
+            "chunk".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("chunk", &params, 2)?;
+                let items: Vec<Type> = list(&params[0])?.iter().cloned().collect();
+                let size = count(&params[1])?;
+                if size == 0 {
+                    return Err(PravdaError::new(
+                        ErrorKind::Value,
+                        "the size should be positive",
+                    ));
+                }
+                Ok(Type::List(
+                    items
+                        .chunks(size)
+                        .map(|chunk| Type::List(chunk.iter().cloned().collect()))
+                        .collect(),
+                ))
+            })),
+        ),
+        (
+            "window".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("window", &params, 2)?;
+                let items: Vec<Type> = list(&params[0])?.iter().cloned().collect();
+                let size = count(&params[1])?;
+                if size == 0 {
+                    return Err(PravdaError::new(
+                        ErrorKind::Value,
+                        "the size should be positive",
+                    ));
+                }
+                Ok(Type::List(
+                    items
+                        .windows(size)
+                        .map(|window| Type::List(window.iter().cloned().collect()))
+                        .collect(),
+                ))
+            })),
+        ),
+        (
+            "unique".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("unique", &params, 1)?;
+                // The values seen already are kept sorted, so that any value can be checked
+                let mut seen: Vec<Type> = Vec::new();
+                let mut result = Vector::new();
+                for item in list(&params[0])? {
+                    if let Err(index) = seen.binary_search(item) {
+                        seen.insert(index, item.clone());
+                        result.push_back(item.clone());
+                    }
+                }
+                Ok(Type::List(result))
+            })),
+        ),
+        (
+            "sum".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("sum", &params, 1)?;
+                let mut result = Type::Integer(Int::Small(0));
+                for item in list(&params[0])? {
+                    result = number::add(&result, item)?;
+                }
+                Ok(result)
+            })),
+        ),
+        (
+            "product".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("product", &params, 1)?;
+                let mut result = Type::Integer(Int::Small(1));
+                for item in list(&params[0])? {
+                    result = number::mul(&result, item)?;
+                }
+                Ok(result)
+            })),
+        ),
+        (
+            "foldl".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("foldl", &params, 3)?;
+                let function = params[2].get_function()?;
+                let mut result = params[1].clone();
+                for item in list(&params[0])? {
+                    result = call_function(function.clone(), vec![result, item.clone()], memory)?;
+                }
+                Ok(result)
+            })),
+        ),
+        (
+            "foldr".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("foldr", &params, 3)?;
+                let function = params[2].get_function()?;
+                let mut result = params[1].clone();
+                for item in list(&params[0])?.iter().rev() {
+                    result = call_function(function.clone(), vec![item.clone(), result], memory)?;
+                }
+                Ok(result)
+            })),
+        ),
+        (
+            "scanl".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("scanl", &params, 3)?;
+                let function = params[2].get_function()?;
+                let mut result = vector![params[1].clone()];
+                for item in list(&params[0])? {
+                    let previous = result.back().unwrap().clone();
+                    let value =
+                        call_function(function.clone(), vec![previous, item.clone()], memory)?;
+                    result.push_back(value);
+                }
+                Ok(Type::List(result))
+            })),
+        ),
+        (
+            "scanr".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("scanr", &params, 3)?;
+                let function = params[2].get_function()?;
+                let mut result = vector![params[1].clone()];
+                for item in list(&params[0])?.iter().rev() {
+                    let previous = result.front().unwrap().clone();
+                    let value =
+                        call_function(function.clone(), vec![item.clone(), previous], memory)?;
+                    result.push_front(value);
+                }
+                Ok(Type::List(result))
+            })),
+        ),
+        (
+            "reduce1".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("reduce1", &params, 2)?;
+                let function = params[1].get_function()?;
+                // The first element is the initial value, so the list shouldn't be empty
+                let mut items = list(&params[0])?.iter();
+                let Some(first) = items.next() else {
+                    return Err(PravdaError::new(
+                        ErrorKind::Value,
+                        "can't reduce empty list without initial value",
+                    ));
+                };
+                let mut result = first.clone();
+                for item in items {
+                    result = call_function(function.clone(), vec![result, item.clone()], memory)?;
+                }
+                Ok(result)
+            })),
+        ),
+        (
+            "min".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                extreme("min", &params, Ordering::Less)
+            })),
+        ),
+        (
+            "max".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                extreme("max", &params, Ordering::Greater)
+            })),
+        ),
+        (
+            "cons".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("cons", &params, 2)?;
+                let mut items = match &params[1] {
+                    Type::Null => Vector::new(),
+                    other => list(other)?.clone(),
+                };
+                items.push_front(params[0].clone());
+                Ok(Type::List(items))
+            })),
+        ),
+        (
+            "append".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("append", &params, 1)?;
+                // The lists are concatenated, and other value is added as an element
+                let mut result = Vector::new();
+                for param in params {
+                    match param {
+                        Type::List(items) => result.append(items),
+                        other => result.push_back(other),
+                    }
+                }
+                Ok(Type::List(result))
+            })),
+        ),
+    ]
+}
@@ -1,16 +1,72 @@
 //! This is interpreter of Pravda programming language
-use clap::Parser;
+mod compiler;
+mod diagnostic;
+mod dict;
+mod env;
+mod error;
+mod format;
+mod fs;
+mod lexer;
+mod list;
+mod math;
+mod number;
+mod order;
+mod parser;
+mod random;
+mod regex;
+mod set;
+mod string;
+mod vm;
+
+use clap::builder::RangedU64ValueParser;
+use clap::{Parser, ValueEnum};
+use dict::{dict_to_string, Dict, Key};
 use dirs::home_dir;
+use env::{Captured, Env};
+use error::{ErrorKind, PravdaError};
+use format::format_value;
+use im_rc::{vector, HashMap as ImHashMap, Vector};
+use lexer::{escape, Span};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+use number::{complex_to_string, float_to_string, rational_to_string, Int};
+use parser::{parse, program_to_string, Node, NodeKind, Part, Stmt, StmtKind};
 use pyo3::prelude::*;
-use pyo3::types::{PyAny, PyDict};
+use pyo3::types::{PyAny, PyBool, PyComplex, PyDict, PyLong, PySet};
 use rustyline::DefaultEditor;
+use set::{set_to_string, to_set};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::env::args;
 use std::fs::read_to_string;
 use std::path::Path;
+use std::process::exit;
+use std::rc::Rc;
+use std::thread;
 
 const VERSION: &str = "0.7.2";
 
+/// Maximum depth of the function calling by default
+const RECURSION_LIMIT: usize = 1000;
+
+/// Stack size of the thread that runs the program
+/// It should be enough to reach the recursion limit
+const STACK_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Stack that a level of the function calling may use at most,
+/// and the unoptimized build uses much more than the release one
+const FRAME_SIZE: usize = if cfg!(debug_assertions) {
+    128 * 1024
+} else {
+    64 * 1024
+};
+
+/// Upper bound of the recursion limit, that the stack can hold without overflow
+/// It's 16384 in the release build, and 8192 in the debug build
+const MAX_RECURSION_LIMIT: usize = STACK_SIZE / FRAME_SIZE;
+
 #[derive(Parser, Debug)]
 #[command(
     name = "Pravda",
@@ -35,16 +91,62 @@ struct Cli {
     /// Run passed string as code
     #[arg(short = 'l', value_name = "ONE LINER", long)]
     one_liner: Option<String>,
+
+    /// Maximum depth of the function calling, that's capped by the stack size
+    #[arg(
+        short = 'r',
+        value_name = "DEPTH",
+        long,
+        default_value_t = RECURSION_LIMIT,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..=MAX_RECURSION_LIMIT as u64),
+    )]
+    recursion_limit: usize,
+
+    /// How to run the program
+    #[arg(short = 'b', long, value_enum, default_value_t = Backend::Tree)]
+    backend: Backend,
+
+    /// Seed of the random numbers, to make them reproducible
+    #[arg(short = 's', value_name = "SEED", long)]
+    seed: Option<u64>,
+}
+
+/// Evaluator that runs the program
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Backend {
+    /// Walk the syntax tree directly, that's the reference of the behavior
+    Tree,
+    /// Compile to bytecode and run it on the virtual machine, that's still experimental
+    Vm,
 }
 
 /// The entry point
 fn main() {
-    let memory = &mut builtin_functions();
+    // Run on the thread that has large stack, for deep recursion
+    let runner = thread::Builder::new().stack_size(STACK_SIZE).spawn(run);
+    match runner.map(|handle| handle.join()) {
+        Ok(Ok(())) => {}
+        Ok(Err(_)) => exit(1),
+        Err(err) => {
+            eprintln!("Error! it fault to start the interpreter: {err}");
+            exit(1);
+        }
+    }
+}
+
+/// Run the interpreter by the command-line arguments
+fn run() {
+    let memory = &Env::new(builtin_functions());
 
     let cli = Cli::parse();
+    LIMIT.with(|limit| limit.set(cli.recursion_limit));
+    BACKEND.with(|backend| backend.set(cli.backend));
+    if let Some(seed) = cli.seed {
+        random::seed(seed);
+    }
     if let (Some(args), _) | (_, Some(args)) = (cli.args_option, cli.args_position) {
-        memory.insert(
-            "args".to_string(),
+        memory.define(
+            "args",
             Type::List(args.iter().map(|i| Type::String(i.to_owned())).collect()),
         );
     }
@@ -52,13 +154,23 @@ fn main() {
     if let Some(path) = cli.file {
         // Run from script file
         if let Ok(code) = read_to_string(Path::new(&path)) {
-            run_program(code, memory);
+            if let Err(err) = run_code(&path, &code, memory) {
+                eprintln!("{err}");
+                exit(1);
+            }
         } else {
-            eprintln!("Error! it fault to open the script file")
+            eprintln!("Error! it fault to open the script file");
+            exit(1);
         }
     } else if let Some(code) = cli.one_liner {
         // Run from one-liner code
-        println!("{}", run_program(code, memory).get_symbol());
+        match run_code("<one-liner>", &code, memory) {
+            Ok(result) => println!("{}", result.get_symbol()),
+            Err(err) => {
+                eprintln!("{err}");
+                exit(1);
+            }
+        }
     } else {
         println!("Pravda {VERSION}");
         let mut rl = DefaultEditor::new().unwrap();
@@ -71,211 +183,257 @@ fn main() {
                 if enter.is_empty() {
                     break;
                 }
-                code += &format!("{enter} ");
+                code += &format!("{enter}\n");
                 rl.add_history_entry(&enter).unwrap_or_default();
             }
 
             if !code.is_empty() {
-                println!("{}", run_program(code, memory).get_symbol());
+                match run_code("<repl>", &code, memory) {
+                    Ok(result) => println!("{}", result.get_symbol()),
+                    Err(err) => eprintln!("{err}"),
+                }
             }
         }
     }
 }
 
 fn builtin_functions() -> HashMap<String, Type> {
-    HashMap::from([
+    let mut functions = HashMap::from([
         ("new-line".to_string(), Type::String("\n".to_string())),
         ("tab".to_string(), Type::String("\t".to_string())),
         ("double-quote".to_string(), Type::String("\"".to_string())),
         (
             "+".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                let params: Vec<f64> = params.iter().map(|i| i.get_number()).collect();
-                let mut result: f64 = if let Some(result) = params.first() {
-                    result.to_owned()
-                } else {
-                    return Type::Null;
-                };
-                for i in params[1..params.len()].to_vec().iter() {
-                    result += i;
-                }
-                Type::Number(result)
+                check_arity("+", &params, 1)?;
+                fold_numbers(&params, number::add)
             })),
         ),
         (
             "-".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                let params: Vec<f64> = params.iter().map(|i| i.get_number()).collect();
+                check_arity("-", &params, 1)?;
                 if params.len() == 1 {
-                    Type::Number(-params[0])
+                    number::negate(&params[0])
                 } else {
-                    let mut result: f64 = if let Some(result) = params.first() {
-                        result.to_owned()
-                    } else {
-                        return Type::Null;
-                    };
-                    for i in params[1..params.len()].to_vec().iter() {
-                        result -= i;
-                    }
-                    Type::Number(result)
+                    fold_numbers(&params, number::sub)
                 }
             })),
         ),
         (
             "*".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                let params: Vec<f64> = params.iter().map(|i| i.get_number()).collect();
-                let mut result: f64 = if let Some(result) = params.first() {
-                    result.to_owned()
-                } else {
-                    return Type::Null;
-                };
-                for i in params[1..params.len()].to_vec().iter() {
-                    result *= i;
-                }
-                Type::Number(result)
+                check_arity("*", &params, 1)?;
+                fold_numbers(&params, number::mul)
             })),
         ),
         (
             "/".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                let params: Vec<f64> = params.iter().map(|i| i.get_number()).collect();
-                let mut result: f64 = if let Some(result) = params.first() {
-                    result.to_owned()
-                } else {
-                    return Type::Null;
-                };
-                for i in params[1..params.len()].to_vec().iter() {
-                    result /= i;
-                }
-                Type::Number(result)
+                check_arity("/", &params, 1)?;
+                fold_numbers(&params, number::div)
+            })),
+        ),
+        (
+            "div".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("div", &params, 1)?;
+                fold_numbers(&params, number::div_floor)
             })),
         ),
         (
             "%".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                let params: Vec<f64> = params.iter().map(|i| i.get_number()).collect();
-                let mut result: f64 = if let Some(result) = params.first() {
-                    result.to_owned()
-                } else {
-                    return Type::Null;
-                };
-                for i in params[1..params.len()].to_vec().iter() {
-                    result %= i;
-                }
-                Type::Number(result)
+                check_arity("%", &params, 1)?;
+                fold_numbers(&params, number::modulo)
             })),
         ),
         (
             "^".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                let params: Vec<f64> = params.iter().map(|i| i.get_number()).collect();
-                let mut result: f64 = if let Some(result) = params.first() {
-                    result.to_owned()
-                } else {
-                    return Type::Null;
-                };
-                for i in params[1..params.len()].to_vec().iter() {
-                    result = result.powf(*i);
-                }
-                Type::Number(result)
+                check_arity("^", &params, 1)?;
+                fold_numbers(&params, number::pow)
             })),
         ),
         (
             "equal".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                Type::Bool({
-                    let params: Vec<String> = params.iter().map(|i| i.get_symbol()).collect();
-                    params.windows(2).all(|window| window[0] == window[1])
-                })
+                Ok(Type::Bool(
+                    params.windows(2).all(|window| window[0] == window[1]),
+                ))
+            })),
+        ),
+        (
+            "not-equal".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("not-equal", &params, 2)?;
+                Ok(Type::Bool(
+                    params.windows(2).all(|window| window[0] != window[1]),
+                ))
             })),
         ),
         (
             "less-than".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                Type::Bool({
-                    let params: Vec<f64> = params.iter().map(|i| i.get_number()).collect();
-                    params.windows(2).all(|window| window[0] < window[1])
-                })
+                compare_values(&params, Ordering::is_lt)
             })),
         ),
         (
             "greater-than".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                Type::Bool({
-                    let params: Vec<f64> = params.iter().map(|i| i.get_number()).collect();
-                    params.windows(2).all(|window| window[0] > window[1])
-                })
+                compare_values(&params, Ordering::is_gt)
+            })),
+        ),
+        (
+            "less-equal".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                compare_values(&params, Ordering::is_le)
+            })),
+        ),
+        (
+            "greater-equal".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                compare_values(&params, Ordering::is_ge)
+            })),
+        ),
+        (
+            "compare".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("compare", &params, 2)?;
+                Ok(Type::Integer(Int::from(params[0].cmp(&params[1]) as i64)))
+            })),
+        ),
+        (
+            "rational".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("rational", &params, 2)?;
+                let numerator = number::ratio(&params[0])?;
+                let denominator = number::ratio(&params[1])?;
+                if denominator.is_zero() {
+                    return Err(PravdaError::new(ErrorKind::Value, "division by zero"));
+                }
+                Ok(number::from_ratio(numerator / denominator))
+            })),
+        ),
+        (
+            "complex".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("complex", &params, 2)?;
+                let real = number::float(&params[0])?;
+                let imaginary = number::float(&params[1])?;
+                Ok(Type::Complex(Complex64::new(real, imaginary)))
+            })),
+        ),
+        (
+            "numerator".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("numerator", &params, 1)?;
+                let value = number::ratio(&params[0])?;
+                Ok(Type::Integer(Int::from(value.numer().clone())))
+            })),
+        ),
+        (
+            "denominator".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("denominator", &params, 1)?;
+                let value = number::ratio(&params[0])?;
+                Ok(Type::Integer(Int::from(value.denom().clone())))
+            })),
+        ),
+        (
+            "real-part".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("real-part", &params, 1)?;
+                match &params[0] {
+                    Type::Complex(value) => Ok(Type::Number(value.re)),
+                    other => number::number(other),
+                }
+            })),
+        ),
+        (
+            "imag-part".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("imag-part", &params, 1)?;
+                match &params[0] {
+                    Type::Complex(value) => Ok(Type::Number(value.im)),
+                    other => number::number(other).map(|_| Type::Integer(Int::Small(0))),
+                }
+            })),
+        ),
+        (
+            "magnitude".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("magnitude", &params, 1)?;
+                match &params[0] {
+                    Type::Complex(value) => Ok(Type::Number(value.norm())),
+                    other
+                        if number::compare(other, &Type::Integer(Int::Small(0)))?
+                            == Some(Ordering::Less) =>
+                    {
+                        number::negate(other)
+                    }
+                    other => number::number(other),
+                }
             })),
         ),
         (
             "or".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                Type::Bool({
+                Ok(Type::Bool({
                     let params: Vec<bool> = params.iter().map(|i| i.get_bool()).collect();
                     params.iter().any(|&x| x)
-                })
+                }))
             })),
         ),
         (
             "and".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                Type::Bool({
+                Ok(Type::Bool({
                     let params: Vec<bool> = params.iter().map(|i| i.get_bool()).collect();
                     params.iter().all(|&x| x)
-                })
+                }))
             })),
         ),
         (
             "not".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                if !params.is_empty() {
-                    Type::Bool(!params[0].get_bool())
-                } else {
-                    Type::Null
-                }
+                check_arity("not", &params, 1)?;
+                Ok(Type::Bool(!params[0].get_bool()))
             })),
         ),
         (
             "concat".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
                 let params: Vec<String> = params.iter().map(|i| i.get_string()).collect();
-                Type::String(params.join(""))
+                Ok(Type::String(params.join("")))
             })),
         ),
         (
             "split".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                Type::List({
-                    let text = if let Some(count) = params.first() {
-                        count.get_string().to_owned()
-                    } else {
-                        return Type::Null;
-                    };
-                    let key = if let Some(count) = params.get(1) {
-                        count.get_string()
-                    } else {
-                        return Type::Null;
-                    };
-
+                check_arity("split", &params, 2)?;
+                let text = params[0].get_string();
+                let key = params[1].get_string();
+                Ok(Type::List(
                     text.split(&key)
                         .map(|i| Type::String(i.to_string()))
-                        .collect()
-                })
+                        .collect(),
+                ))
             })),
         ),
         (
             "input".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                Type::String({
-                    let mut rl = DefaultEditor::new().unwrap();
+                let mut rl = DefaultEditor::new()
+                    .map_err(|err| PravdaError::new(ErrorKind::Io, err.to_string()))?;
+                Ok(Type::String(
                     rl.readline(&if let Some(prompt) = params.first() {
                         prompt.get_string()
                     } else {
                         "".to_string()
                     })
-                    .unwrap_or_default()
-                })
+                    .unwrap_or_default(),
+                ))
             })),
         ),
         (
@@ -289,285 +447,262 @@ fn builtin_functions() -> HashMap<String, Type> {
                         .collect::<Vec<String>>()
                         .join("")
                 );
-                Type::Null
+                Ok(Type::Null)
             })),
         ),
         (
             "list".to_string(),
-            Type::Function(Function::BuiltIn(|params, _| Type::List(params))),
+            Type::Function(Function::BuiltIn(|params, _| Ok(Type::List(params.into())))),
         ),
         (
             "car".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                if let Some(list) = params.first() {
-                    if let Some(car) = list.get_list().first() {
-                        car.clone()
-                    } else {
-                        Type::Null
-                    }
+                check_arity("car", &params, 1)?;
+                if let Some(car) = params[0].get_list().front() {
+                    Ok(car.clone())
                 } else {
-                    Type::Null
+                    Ok(Type::Null)
                 }
             })),
         ),
         (
             "cdr".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                if let Some(list) = params.first() {
-                    if list.get_list().len() >= 2 {
-                        Type::List(list.get_list()[1..list.get_list().len()].to_vec())
-                    } else {
-                        Type::Null
-                    }
+                check_arity("cdr", &params, 1)?;
+                let list = params[0].get_list();
+                if list.len() >= 2 {
+                    Ok(Type::List(list.skip(1)))
                 } else {
-                    Type::Null
+                    Ok(Type::Null)
                 }
             })),
         ),
         (
             "len".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                if let Some(Type::List(list)) = params.first() {
-                    Type::Number(list.len() as f64)
-                } else if let Some(Type::String(string)) = params.first() {
-                    Type::Number(string.chars().count() as f64)
-                } else {
-                    Type::Null
+                check_arity("len", &params, 1)?;
+                match &params[0] {
+                    Type::List(list) => Ok(Type::Integer(Int::from(list.len()))),
+                    Type::String(string) => Ok(Type::Integer(Int::from(string.chars().count()))),
+                    Type::Dict(dict) => Ok(Type::Integer(Int::from(dict.len()))),
+                    Type::Set(set) => Ok(Type::Integer(Int::from(set.len()))),
+                    other => Err(type_error("list, string, dict or set", other)),
                 }
             })),
         ),
         (
             "range".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                if params.len() == 1 {
-                    let mut range: Vec<Type> = vec![];
-                    let mut current: f64 = 0.0;
-                    while current < params[0].get_number() {
-                        range.push(Type::Number(current));
-                        current += 1.0;
-                    }
-                    Type::List(range)
-                } else if params.len() == 2 {
-                    let mut range: Vec<Type> = vec![];
-                    let mut current: f64 = params[0].get_number();
-                    while current < params[1].get_number() {
-                        range.push(Type::Number(current));
-                        current += 1.0;
-                    }
-                    Type::List(range)
-                } else if params.len() >= 3 {
-                    let mut range: Vec<Type> = vec![];
-                    let mut current: f64 = params[0].get_number();
-                    while current < params[1].get_number() {
-                        range.push(Type::Number(current));
-                        current += params[2].get_number();
+                check_arity("range", &params, 1)?;
+                let zero = Type::Integer(Int::Small(0));
+                let one = Type::Integer(Int::Small(1));
+                let (start, end, step) = match params.as_slice() {
+                    [end] => (&zero, end, &one),
+                    [start, end] => (start, end, &one),
+                    [start, end, step, ..] => (start, end, step),
+                    [] => unreachable!(),
+                };
+                if number::compare(step, &zero)? != Some(Ordering::Greater) {
+                    return Err(PravdaError::new(
+                        ErrorKind::Value,
+                        "the step of `range` should be positive",
+                    ));
+                }
+
+                // Each element is calculated from the start, so that the error doesn't accumulate
+                let mut range = Vector::new();
+                loop {
+                    let count = Type::Integer(Int::from(range.len()));
+                    let current = number::add(start, &number::mul(step, &count)?)?;
+                    if number::compare(&current, end)? != Some(Ordering::Less) {
+                        break Ok(Type::List(range));
                     }
-                    Type::List(range)
-                } else {
-                    Type::Null
+                    range.push_back(current);
                 }
             })),
         ),
         (
             "map".to_string(),
             Type::Function(Function::BuiltIn(|params, memory| {
-                if params.len() >= 2 {
-                    let func = if let Type::Function(func) = params[1].clone() {
-                        func
-                    } else {
-                        return Type::Null;
-                    };
-                    let memory = memory.clone();
-                    Type::List(
-                        params[0]
-                            .get_list()
-                            .iter()
-                            .map(|i| call_function(func.clone(), vec![i.clone()], &memory))
-                            .collect(),
-                    )
-                } else {
-                    Type::Null
+                check_arity("map", &params, 2)?;
+                let func = params[1].get_function()?;
+                if let Type::Dict(dict) = &params[0] {
+                    // The function takes the key and value, and returns the new entry
+                    let mut result = Dict::new();
+                    for (key, value) in dict {
+                        let params = vec![key.to_type(), value.clone()];
+                        let (key, value) =
+                            dict::entry(&call_function(func.clone(), params, memory)?)?;
+                        result.insert(key, value);
+                    }
+                    return Ok(Type::Dict(result));
+                }
+                let mut result = Vector::new();
+                for item in params[0].get_list() {
+                    result.push_back(call_function(func.clone(), vec![item], memory)?);
                 }
+                Ok(Type::List(result))
             })),
         ),
         (
             "filter".to_string(),
             Type::Function(Function::BuiltIn(|params, memory| {
-                if params.len() >= 2 {
-                    let func = if let Type::Function(func) = params[1].clone() {
-                        func
-                    } else {
-                        return Type::Null;
-                    };
-                    let memory = memory.clone();
-                    let mut result = Vec::new();
-
-                    for item in params[0].get_list() {
-                        if call_function(func.clone(), vec![item.clone()], &memory).get_bool() {
-                            result.push(item);
+                check_arity("filter", &params, 2)?;
+                let func = params[1].get_function()?;
+                if let Type::Dict(dict) = &params[0] {
+                    let mut result = Dict::new();
+                    for (key, value) in dict {
+                        let params = vec![key.to_type(), value.clone()];
+                        if call_function(func.clone(), params, memory)?.get_bool() {
+                            result.insert(key.clone(), value.clone());
                         }
                     }
-                    Type::List(result)
-                } else {
-                    Type::Null
+                    return Ok(Type::Dict(result));
+                }
+                let mut result = Vector::new();
+                for item in params[0].get_list() {
+                    if call_function(func.clone(), vec![item.clone()], memory)?.get_bool() {
+                        result.push_back(item);
+                    }
                 }
+                Ok(Type::List(result))
             })),
         ),
         (
             "reduce".to_string(),
-            Type::Function(Function::BuiltIn(|params, memory| {
-                if params.len() >= 3 {
-                    let func = if let Type::Function(func) = params[2].clone() {
-                        func
-                    } else {
-                        return Type::Null;
-                    };
-                    let variable = if let Type::Symbol(variable) = params[1].clone() {
-                        variable
-                    } else {
-                        return Type::Null;
-                    };
-
-                    let mut memory = memory.clone();
-                    let mut result = memory.get(&variable).unwrap_or(&Type::Null).to_owned();
+            Type::Function(Function::Scoped(|params, memory| {
+                check_arity("reduce", &params, 3)?;
+                if !REDUCE_WARNED.replace(true) {
+                    WARNING.set(Some(
+                        "`reduce` with the accumulator symbol is deprecated, use `foldl` instead"
+                            .to_string(),
+                    ));
+                }
+                let func = params[2].get_function()?;
+                let Type::Symbol(variable) = params[1].clone() else {
+                    return Err(type_error("symbol", &params[1]));
+                };
 
-                    for item in params[0].get_list() {
-                        result = call_function(func.clone(), vec![item.clone()], &memory);
-                        memory.insert(variable.clone(), result.clone());
+                // The accumulator is visible for the function while folding
+                let previous = memory.remove(&variable);
+                let mut result = previous
+                    .clone()
+                    .or_else(|| memory.get(&variable))
+                    .unwrap_or(Type::Null);
+                let mut failure = None;
+                for item in params[0].get_list() {
+                    memory.define(&variable, result.clone());
+                    match call_function(func.clone(), vec![item.clone()], memory) {
+                        Ok(value) => result = value,
+                        Err(err) => {
+                            failure = Some(err);
+                            break;
+                        }
                     }
-                    result
-                } else {
-                    Type::Null
+                }
+                memory.remove(&variable);
+                if let Some(previous) = previous {
+                    memory.define(&variable, previous);
+                }
+                match failure {
+                    Some(err) => Err(err),
+                    None => Ok(result),
                 }
             })),
         ),
         (
             "for".to_string(),
             Type::Function(Function::BuiltIn(|params, memory| {
-                if params.len() >= 2 {
-                    let func = if let Type::Function(func) = params[1].clone() {
-                        func
-                    } else {
-                        return Type::Null;
-                    };
-                    let memory = memory.clone();
-
-                    let mut temp = Type::Null;
-                    for item in params[0].get_list() {
-                        temp = call_function(func.clone(), vec![item.clone()], &memory);
-                    }
-                    temp
-                } else {
-                    Type::Null
+                check_arity("for", &params, 2)?;
+                let func = params[1].get_function()?;
+                let mut temp = Type::Null;
+                for item in params[0].get_list() {
+                    temp = call_function(func.clone(), vec![item.clone()], memory)?;
                 }
+                Ok(temp)
             })),
         ),
         (
             "while".to_string(),
-            Type::Function(Function::BuiltIn(|params, memory| {
-                if params.len() >= 2 {
-                    let cond = if let Type::Expr(expr) = params[0].clone() {
-                        expr
-                    } else {
-                        return Type::Null;
-                    };
-                    let block = if let Type::Block(block) = params[1].clone() {
-                        block
-                    } else {
-                        return Type::Null;
-                    };
+            Type::Function(Function::Scoped(|params, memory| {
+                check_arity("while", &params, 2)?;
+                let Type::Expr(cond) = params[0].clone() else {
+                    return Err(type_error("expr", &params[0]));
+                };
+                let Type::Block(block) = params[1].clone() else {
+                    return Err(type_error("block", &params[1]));
+                };
 
-                    let mut memory = memory.clone();
-                    let mut temp = Type::Null;
-                    while eval_expr(cond.clone(), &memory).get_bool() {
-                        temp = run_program(block.clone(), &mut memory);
-                    }
-                    temp
-                } else {
-                    Type::Null
+                let scope = &Env::child(memory);
+                let mut temp = Type::Null;
+                while eval_expr(&cond, scope)?.get_bool() {
+                    temp = run_program(&block, scope)?;
                 }
+                Ok(temp)
             })),
         ),
         (
             "if".to_string(),
-            Type::Function(Function::BuiltIn(|params, memory| {
-                let mut memory = memory.clone();
-                if params.len() >= 2 {
-                    if params[0].get_bool() {
-                        match params[1].clone() {
-                            Type::Expr(code) => eval_expr(code, &memory),
-                            Type::Block(block) => run_program(block, &mut memory),
-                            other => other,
-                        }
-                    } else if params.len() >= 3 {
-                        match params[2].clone() {
-                            Type::Expr(code) => eval_expr(code, &memory),
-                            Type::Block(block) => run_program(block, &mut memory),
-                            other => other,
-                        }
-                    } else {
-                        Type::Null
-                    }
+            Type::Function(Function::Control(|params, _| {
+                check_arity("if", &params, 2)?;
+                if params[0].get_bool() {
+                    Ok(Flow::Eval(params[1].clone()))
+                } else if params.len() >= 3 {
+                    Ok(Flow::Eval(params[2].clone()))
                 } else {
-                    Type::Null
+                    Ok(Flow::Value(Type::Null))
                 }
             })),
         ),
         (
             "eval".to_string(),
-            Type::Function(Function::BuiltIn(|params, memory| {
-                let mut memory = memory.clone();
-                if !params.is_empty() {
-                    match params[0].clone() {
-                        Type::Expr(code) => eval_expr(code, &memory),
-                        Type::Block(block) => run_program(block, &mut memory),
-                        Type::Symbol(name) => memory.get(&name).unwrap_or(&Type::Null).to_owned(),
-                        other => other,
-                    }
-                } else {
-                    Type::Null
+            Type::Function(Function::Control(|params, _| {
+                check_arity("eval", &params, 1)?;
+                match params[0].clone() {
+                    Type::Symbol(name) => Ok(Flow::Lookup(name)),
+                    code => Ok(Flow::Eval(code)),
                 }
             })),
         ),
         (
             "load".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                if !params.is_empty() {
-                    let identify = params[0].get_string();
-
-                    if let (_, Ok(code)) | (Ok(code), _) = (
-                        read_to_string(Path::new(&identify.clone())),
-                        read_to_string(home_dir().unwrap().join(Path::new(&identify.clone()))),
-                    ) {
-                        if params[0].get_string().ends_with(".pvd") {
-                            Type::Function(Function::Module(code))
-                        } else if params[0].get_string().ends_with(".py") {
-                            let lines: Vec<String> =
-                                code.split("\n").map(|s| s.to_string()).collect();
-                            let (depend, code): (Vec<String>, String) = (
-                                {
-                                    if lines[0].to_string().trim().starts_with("import ") {
-                                        let import: Vec<String> = lines[0]
-                                            .replace("import ", "")
-                                            .split(",")
-                                            .map(|i| i.trim().to_string())
-                                            .collect();
-                                        import
-                                    } else {
-                                        vec![]
-                                    }
-                                },
-                                code,
-                            );
-                            Type::Function(Function::Python(code, depend))
-                        } else {
-                            Type::Null
-                        }
-                    } else {
-                        Type::Null
+                check_arity("load", &params, 1)?;
+                let identify = params[0].get_string();
+
+                let code = match (
+                    read_to_string(Path::new(&identify)),
+                    home_dir().map(|home| read_to_string(home.join(Path::new(&identify)))),
+                ) {
+                    (_, Some(Ok(code))) | (Ok(code), _) => code,
+                    (Err(err), _) => {
+                        return Err(PravdaError::new(
+                            ErrorKind::Io,
+                            format!("it fault to load {identify}: {err}"),
+                        ))
                     }
+                };
+
+                if identify.ends_with(".pvd") {
+                    let program = parse(&identify, &code)?;
+                    Ok(Type::Function(Function::Module(Rc::new(program))))
+                } else if identify.ends_with(".py") {
+                    let lines: Vec<String> = code.split("\n").map(|s| s.to_string()).collect();
+                    let depend: Vec<String> = if lines[0].trim().starts_with("import ") {
+                        lines[0]
+                            .replace("import ", "")
+                            .split(",")
+                            .map(|i| i.trim().to_string())
+                            .collect()
+                    } else {
+                        vec![]
+                    };
+                    Ok(Type::Function(Function::Python(code, depend)))
                 } else {
-                    Type::Null
+                    Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!("{identify} is neither Pravda module (.pvd) nor Python (.py)"),
+                    ))
                 }
             })),
         ),
@@ -578,147 +713,475 @@ fn builtin_functions() -> HashMap<String, Type> {
         (
             "cast".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                if params.len() >= 2 {
-                    match params[1].get_string().as_str() {
-                        "string" => Type::String(params[0].get_string()),
-                        "number" => Type::Number(params[0].get_number()),
-                        "symbol" => Type::Symbol(params[0].get_symbol()),
-                        "list" => Type::List(params[0].get_list()),
-                        "bool" => Type::Bool(params[0].get_bool()),
-                        _ => Type::Null,
-                    }
-                } else {
-                    Type::Null
+                check_arity("cast", &params, 2)?;
+                match params[1].get_string().as_str() {
+                    "string" => Ok(Type::String(params[0].get_string())),
+                    "number" => match &params[0] {
+                        Type::Integer(_)
+                        | Type::Rational(_)
+                        | Type::Number(_)
+                        | Type::Complex(_) => Ok(params[0].clone()),
+                        other => Ok(number::parse_number(other.get_string().trim())
+                            .unwrap_or(Type::Number(other.get_number()))),
+                    },
+                    "integer" => match &params[0] {
+                        Type::Integer(_) => Ok(params[0].clone()),
+                        Type::String(value) => match Int::parse(value.trim()) {
+                            Some(value) => Ok(Type::Integer(value)),
+                            None => Ok(Type::Integer(
+                                Int::from_f64(params[0].get_number().trunc())
+                                    .unwrap_or(Int::Small(0)),
+                            )),
+                        },
+                        other => Ok(Type::Integer(
+                            Int::from_f64(other.get_number().trunc()).unwrap_or(Int::Small(0)),
+                        )),
+                    },
+                    "float" => Ok(Type::Number(params[0].get_number())),
+                    "symbol" => Ok(Type::Symbol(params[0].get_symbol())),
+                    "list" => Ok(Type::List(params[0].get_list())),
+                    "bool" => Ok(Type::Bool(params[0].get_bool())),
+                    "set" => Ok(Type::Set(to_set(params[0].get_list())?)),
+                    "dict" => Ok(Type::Dict(
+                        params[0]
+                            .get_list()
+                            .iter()
+                            .map(dict::entry)
+                            .collect::<Result<Dict, PravdaError>>()?,
+                    )),
+                    other => Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!("can't cast to unknown type `{other}`"),
+                    )),
                 }
             })),
         ),
         (
             "type".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                if !params.is_empty() {
-                    match params[0] {
-                        Type::Number(_) => Type::String("number".to_string()),
-                        Type::String(_) => Type::String("string".to_string()),
-                        Type::Bool(_) => Type::String("bool".to_string()),
-                        Type::List(_) => Type::String("list".to_string()),
-                        Type::Expr(_) => Type::String("expr".to_string()),
-                        Type::Block(_) => Type::String("block".to_string()),
-                        Type::Symbol(_) => Type::String("symbol".to_string()),
-                        Type::Function(_) => Type::String("function".to_string()),
-                        Type::Null => Type::String("null".to_string()),
-                        Type::Struct(_) => Type::String("struct".to_string()),
-                    }
-                } else {
-                    Type::Null
-                }
+                check_arity("type", &params, 1)?;
+                Ok(Type::String(params[0].type_name().to_string()))
             })),
         ),
         (
             "get-field".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                if params.len() >= 2 {
-                    if let Some(value) = params[0].get_struct().get(&params[1].get_string()) {
-                        value.clone()
-                    } else {
-                        Type::Null
-                    }
+                check_arity("get-field", &params, 2)?;
+                let Type::Struct(value) = &params[0] else {
+                    return Err(type_error("struct", &params[0]));
+                };
+                let name = params[1].get_string();
+                if let Some(value) = value.get(&name) {
+                    Ok(value.clone())
                 } else {
-                    Type::Null
+                    Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!("the struct doesn't have field `{name}`"),
+                    ))
                 }
             })),
         ),
         (
             "set-field".to_string(),
             Type::Function(Function::BuiltIn(|params, _| {
-                if params.len() >= 3 {
-                    let mut value = params[0].get_struct();
-                    value.insert(params[1].get_string(), params[2].clone());
-                    Type::Struct(value)
-                } else {
-                    Type::Null
+                check_arity("set-field", &params, 3)?;
+                let Type::Struct(mut value) = params[0].clone() else {
+                    return Err(type_error("struct", &params[0]));
+                };
+                value.insert(params[1].get_string(), params[2].clone());
+                Ok(Type::Struct(value))
+            })),
+        ),
+        (
+            "get".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("get", &params, 2)?;
+                let dict = dict::dict(&params[0])?;
+                match (dict.get(&Key::new(&params[1])?), params.get(2)) {
+                    (Some(value), _) => Ok(value.clone()),
+                    (None, Some(default)) => Ok(default.clone()),
+                    (None, None) => Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!("the dict doesn't have key {}", params[1].get_symbol()),
+                    )),
                 }
             })),
         ),
         (
-            "exit".to_string(),
-            Type::Function(Function::BuiltIn(|_, _| {
-                std::process::exit(0);
+            "insert".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("insert", &params, 3)?;
+                let mut dict = dict::dict(&params[0])?.clone();
+                dict.insert(Key::new(&params[1])?, params[2].clone());
+                Ok(Type::Dict(dict))
             })),
         ),
-    ])
-}
-
-/// Dynamic data type used in Pravda
-#[derive(Clone, Debug)]
-enum Type {
-    /// Expression
-    ///
-    /// Example:
-    /// ```
-    /// (+ 1 2 (* 3 4))
-    /// ```
-    Expr(String),
-    /// Code block
-    ///
-    /// Example:
-    /// ```
-    /// {
-    ///     x = 5;
-    ///     + x 1
-    /// }
-    /// ```
-    Block(String),
-    /// Symbol
-    /// Used in such a variable, lazy evaluting, etc
-    ///
-    /// Example:
-    /// ```
-    /// lazy(+ 1 2)
-    /// ```
-    Symbol(String),
-    /// Function object
-    ///
-    /// Example:
-    /// ```
-    /// lambda(n -> * n 2)
-    /// ```
-    Function(Function),
-    /// Number that's 64bit float
-    ///
-    /// Example:
-    /// ```
-    /// 3.14
-    /// ```
-    Number(f64),
-    /// Number that's 64bit float
-    ///
-    /// Example:
-    /// ```
-    /// "hello"
-    /// ```
-    String(String),
-    /// Bool value
-    /// Used in logical operating
-    ///
-    /// Example:
-    /// ```
-    /// true
-    /// ```
-    Bool(bool),
-    /// List includes several values
-    ///
-    /// Example:
-    /// ```
-    /// [1 2 "abc"]
-    /// ```
-    List(Vec<Type>),
-    /// User-defined type
-    ///
-    /// Example:
+        (
+            "remove".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("remove", &params, 2)?;
+                let mut dict = dict::dict(&params[0])?.clone();
+                for key in &params[1..] {
+                    dict.remove(&Key::new(key)?);
+                }
+                Ok(Type::Dict(dict))
+            })),
+        ),
+        (
+            "contains".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("contains", &params, 2)?;
+                if let Type::String(text) = &params[0] {
+                    return Ok(Type::Bool(text.contains(string::string(&params[1])?)));
+                }
+                let key = Key::new(&params[1])?;
+                match &params[0] {
+                    Type::Set(set) => Ok(Type::Bool(set.contains(&key))),
+                    other => Ok(Type::Bool(dict::dict(other)?.contains_key(&key))),
+                }
+            })),
+        ),
+        (
+            "union".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("union", &params, 1)?;
+                let mut result = set::set(&params[0])?.clone();
+                for param in &params[1..] {
+                    result = result.union(set::set(param)?.clone());
+                }
+                Ok(Type::Set(result))
+            })),
+        ),
+        (
+            "intersection".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("intersection", &params, 1)?;
+                let mut result = set::set(&params[0])?.clone();
+                for param in &params[1..] {
+                    result = result.intersection(set::set(param)?.clone());
+                }
+                Ok(Type::Set(result))
+            })),
+        ),
+        (
+            "difference".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("difference", &params, 1)?;
+                let mut result = set::set(&params[0])?.clone();
+                for param in &params[1..] {
+                    result = result.relative_complement(set::set(param)?.clone());
+                }
+                Ok(Type::Set(result))
+            })),
+        ),
+        (
+            "symmetric-difference".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("symmetric-difference", &params, 1)?;
+                let mut result = set::set(&params[0])?.clone();
+                for param in &params[1..] {
+                    result = result.symmetric_difference(set::set(param)?.clone());
+                }
+                Ok(Type::Set(result))
+            })),
+        ),
+        (
+            "subset".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("subset", &params, 2)?;
+                let set = set::set(&params[0])?;
+                Ok(Type::Bool(set.is_subset(set::set(&params[1])?)))
+            })),
+        ),
+        (
+            "superset".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("superset", &params, 2)?;
+                let set = set::set(&params[0])?;
+                Ok(Type::Bool(set::set(&params[1])?.is_subset(set)))
+            })),
+        ),
+        (
+            "keys".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("keys", &params, 1)?;
+                let dict = dict::dict(&params[0])?;
+                Ok(Type::List(dict.keys().map(|k| k.to_type()).collect()))
+            })),
+        ),
+        (
+            "values".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("values", &params, 1)?;
+                let dict = dict::dict(&params[0])?;
+                Ok(Type::List(dict.values().cloned().collect()))
+            })),
+        ),
+        (
+            "entries".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("entries", &params, 1)?;
+                let dict = dict::dict(&params[0])?;
+                Ok(Type::List(
+                    dict.iter().map(|(k, v)| dict::to_entry(k, v)).collect(),
+                ))
+            })),
+        ),
+        (
+            "merge".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("merge", &params, 1)?;
+                // The value of the later dictionary wins if the key is duplicated
+                let mut result = Dict::new();
+                for param in &params {
+                    let dict = dict::dict(param)?;
+                    result.extend(dict.iter().map(|(k, v)| (k.clone(), v.clone())));
+                }
+                Ok(Type::Dict(result))
+            })),
+        ),
+        (
+            "raise".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("raise", &params, 1)?;
+                // The error caught by `try` is raised again as it is
+                if let Some(err) = PravdaError::from_value(&params[0]) {
+                    return Err(err);
+                }
+                let message = match &params[0] {
+                    Type::Struct(fields) if fields.contains_key("message") => {
+                        fields["message"].get_string()
+                    }
+                    other => other.get_string(),
+                };
+                Err(PravdaError::new(ErrorKind::User, message).with("value", params[0].clone()))
+            })),
+        ),
+        (
+            "try".to_string(),
+            Type::Function(Function::Scoped(|params, memory| {
+                check_arity("try", &params, 2)?;
+                let handler = params[1].get_function()?;
+                let run = |code: &Type| match code {
+                    Type::Expr(code) => eval_expr(code, memory),
+                    Type::Block(block) => run_program(block, &Env::child(memory)),
+                    other => Ok(other.clone()),
+                };
+
+                let result = match run(&params[0]) {
+                    Ok(result) => Ok(result),
+                    Err(err) => call_function(handler, vec![err.to_value()], memory),
+                };
+                if let Some(finally) = params.get(2) {
+                    run(finally)?;
+                }
+                result
+            })),
+        ),
+        (
+            "recursion-limit".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                // Set the limit if it's passed, and return the current one
+                if let Some(value) = params.first() {
+                    let depth = number::integer(value)?;
+                    let range = 1..=MAX_RECURSION_LIMIT as i64;
+                    match depth.to_i64().filter(|depth| range.contains(depth)) {
+                        Some(depth) => LIMIT.with(|limit| limit.set(depth as usize)),
+                        None => {
+                            return Err(PravdaError::new(
+                                ErrorKind::Value,
+                                format!(
+                                    "recursion limit should be between 1 and {MAX_RECURSION_LIMIT}, not {depth}"
+                                ),
+                            ))
+                        }
+                    }
+                }
+                Ok(Type::Integer(Int::from(LIMIT.with(|limit| limit.get()))))
+            })),
+        ),
+        (
+            "exit".to_string(),
+            Type::Function(Function::BuiltIn(|_, _| {
+                exit(0);
+            })),
+        ),
+    ]);
+    functions.extend(math::builtins());
+    functions.extend(list::builtins());
+    functions.extend(string::builtins());
+    functions.extend(regex::builtins());
+    functions.extend(random::builtins());
+    functions.extend(fs::builtins());
+    functions
+}
+
+/// Check the arguments count of built-in function
+fn check_arity(name: &str, params: &[Type], expected: usize) -> Result<(), PravdaError> {
+    if params.len() < expected {
+        Err(PravdaError::new(
+            ErrorKind::Arity,
+            format!(
+                "`{name}` requires at least {expected} argument{}, but {} given",
+                if expected == 1 { "" } else { "s" },
+                params.len()
+            ),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Error that the value is not expected type
+fn type_error(expected: &str, found: &Type) -> PravdaError {
+    PravdaError::new(
+        ErrorKind::Type,
+        format!("expected {expected}, but found {}", found.type_name()),
+    )
+}
+
+/// Apply the arithmetic operation to the arguments from the left
+fn fold_numbers(
+    params: &[Type],
+    operate: fn(&Type, &Type) -> Result<Type, PravdaError>,
+) -> Result<Type, PravdaError> {
+    let mut result = number::number(&params[0])?;
+    for param in &params[1..] {
+        result = operate(&result, param)?;
+    }
+    Ok(result)
+}
+
+/// Check that each adjacent values are in the order
+/// Numbers are compared by the value, so NaN isn't in any order and complex number is error
+fn compare_values(params: &[Type], accept: fn(Ordering) -> bool) -> Result<Type, PravdaError> {
+    for window in params.windows(2) {
+        let [a, b] = window else { unreachable!() };
+        let order = if number::is_number(a) && number::is_number(b) {
+            number::compare(a, b)?
+        } else {
+            Some(a.cmp(b))
+        };
+        if !order.is_some_and(accept) {
+            return Ok(Type::Bool(false));
+        }
+    }
+    Ok(Type::Bool(true))
+}
+
+/// Dynamic data type used in Pravda
+#[derive(Clone, Debug)]
+enum Type {
+    /// Expression
+    ///
+    /// Example:
+    /// ```
+    /// (+ 1 2 (* 3 4))
+    /// ```
+    Expr(Rc<Node>),
+    /// Code block
+    ///
+    /// Example:
+    /// ```
+    /// {
+    ///     x = 5;
+    ///     + x 1
+    /// }
+    /// ```
+    Block(Rc<Vec<Stmt>>),
+    /// Symbol
+    /// Used in such a variable, lazy evaluting, etc
+    ///
+    /// Example:
+    /// ```
+    /// lazy(+ 1 2)
+    /// ```
+    Symbol(String),
+    /// Function object
+    ///
+    /// Example:
+    /// ```
+    /// lambda(n -> * n 2)
+    /// ```
+    Function(Function),
+    /// Integer of arbitrary precision
+    ///
+    /// Example:
+    /// ```
+    /// 42
+    /// ```
+    Integer(Int),
+    /// Exact fraction of integers
+    ///
+    /// Example:
+    /// ```
+    /// 1/3
+    /// ```
+    Rational(BigRational),
+    /// Complex number of 64bit floats
+    ///
+    /// Example:
+    /// ```
+    /// (+ 1 2i)
+    /// ```
+    Complex(Complex64),
+    /// Number that's 64bit float
+    ///
+    /// Example:
+    /// ```
+    /// 3.14
+    /// ```
+    Number(f64),
+    /// Text of Unicode characters
+    ///
+    /// Example:
+    /// ```
+    /// "hello"
+    /// ```
+    String(String),
+    /// Bool value
+    /// Used in logical operating
+    ///
+    /// Example:
+    /// ```
+    /// true
+    /// ```
+    Bool(bool),
+    /// List includes several values
+    ///
+    /// Example:
     /// ```
     /// [1 2 "abc"]
     /// ```
-    Struct(HashMap<String, Type>),
+    List(Vector<Type>),
+    /// User-defined type
+    ///
+    /// Example:
+    /// ```
+    /// struct{ name = "abc"; age = 2 }
+    /// ```
+    Struct(ImHashMap<String, Type>),
+    /// Dictionary that's ordered by the keys
+    ///
+    /// Example:
+    /// ```
+    /// dict{ "a" -> 1; 2 -> "b" }
+    /// ```
+    Dict(Dict),
+    /// Set of unique values that's ordered
+    ///
+    /// Example:
+    /// ```
+    /// set{ 1 2 3 }
+    /// ```
+    Set(set::Set),
+    /// Compiled regular expression
+    ///
+    /// Example:
+    /// ```
+    /// (regex "[0-9]+")
+    /// ```
+    Regex(::regex::Regex),
     /// Null
     /// Shows there's nothing
     ///
@@ -730,95 +1193,40 @@ enum Type {
 }
 
 impl Type {
-    /// Parse from string
-    fn parse(source: String, memory: &HashMap<String, Type>) -> Type {
-        let mut source = source.trim().to_string();
-        if let Ok(value) = source.parse::<f64>() {
-            // Number value
-            Type::Number(value)
-        } else if let Ok(value) = source.parse::<bool>() {
-            // Bool value
-            Type::Bool(value)
-        } else if source == "null" {
-            // Null value
-            Type::Null
-        } else if source.starts_with('"') && source.starts_with('"') {
-            // String object is surrounded by double quote
-            Type::String({
-                source.remove(source.find('"').unwrap_or_default());
-                source.remove(source.rfind('"').unwrap_or_default());
-                source.to_string()
-            })
-        } else if (source.starts_with("lambda(") || source.starts_with(r"\("))
-            && source.ends_with(")")
-            && source.contains("->")
-        {
-            // Lambda expression
-            source = source.replacen("lambda(", "", 1);
-            source = source.replacen(r"\(", "", 1);
-            source.remove(source.rfind(")").unwrap_or_default());
-            let define: Vec<&str> = source.split("->").collect();
-            Type::Function(Function::UserDefined(vec![(
-                tokenize_expr(define[0].to_string())
-                    .iter()
-                    .map(|i| Type::parse(i.to_string(), memory))
-                    .collect(),
-                (
-                    define[1..define.len()].join("->").to_string(),
-                    HashMap::new(),
-                ),
-            )]))
-        } else if source.starts_with("(") && source.ends_with(")") {
-            // Inner expression is surrounded by parentheses
-            Type::Expr({
-                source.remove(source.find("(").unwrap_or_default());
-                source.remove(source.rfind(")").unwrap_or_default());
-                source
-            })
-        } else if source.starts_with("{") && source.ends_with("}") {
-            // Code block is surrounded by brace
-            Type::Block({
-                source.remove(source.find("{").unwrap_or_default());
-                source.remove(source.rfind("}").unwrap_or_default());
-                source
-            })
-        } else if source.starts_with("[") && source.ends_with("]") {
-            // List object is surrounded by bracket
-            Type::List({
-                source.remove(source.find("[").unwrap_or_default());
-                source.remove(source.rfind("]").unwrap_or_default());
-                tokenize_expr(source)
-                    .iter()
-                    .map(|item| eval_expr(item.to_owned(), memory))
-                    .collect()
-            })
-        } else if source.starts_with("struct{") && source.ends_with("}") {
-            // Lambda expression
-            source = source.replacen("struct{", "", 1);
-            source.remove(source.rfind("}").unwrap_or_default());
-            let mut result = HashMap::new();
-            for i in tokenize_program(source) {
-                if i.len() == 2 {
-                    result.insert(
-                        i[0].trim().to_string(),
-                        eval_expr(i[1].trim().to_string(), memory),
-                    );
-                }
-            }
-            Type::Struct(result)
-        } else {
-            // Other value will be symbol
-            Type::Symbol(source.to_string())
-        }
-    }
-
     fn from_python(result: &PyAny) -> Type {
-        if let Ok(value) = result.extract::<f64>() {
+        // Bool is subclass of int in Python, so it's checked first
+        if let Ok(value) = result.downcast::<PyBool>() {
+            Type::Bool(value.is_true())
+        } else if let Some(value) = result
+            .downcast::<PyLong>()
+            .ok()
+            .and_then(|i| Int::parse(&i.to_string()))
+        {
+            Type::Integer(value)
+        } else if let Ok(value) = result.extract::<f64>() {
             Type::Number(value)
+        } else if let Ok(value) = result.downcast::<PyComplex>() {
+            Type::Complex(Complex64::new(value.real(), value.imag()))
         } else if let Ok(value) = result.extract::<String>() {
             Type::String(value)
         } else if let Ok(value) = result.extract::<bool>() {
             Type::Bool(value)
+        } else if let Ok(value) = result.downcast::<PyDict>() {
+            Type::Dict(
+                value
+                    .iter()
+                    .filter_map(|(k, v)| {
+                        Some((Key::new(&Type::from_python(k)).ok()?, Type::from_python(v)))
+                    })
+                    .collect(),
+            )
+        } else if let Ok(value) = result.downcast::<PySet>() {
+            Type::Set(
+                value
+                    .iter()
+                    .filter_map(|i| Key::new(&Type::from_python(i)).ok())
+                    .collect(),
+            )
         } else if let Ok(value) = result.extract::<Vec<&PyAny>>() {
             Type::List(value.iter().map(|i| Type::from_python(i)).collect())
         } else {
@@ -828,6 +1236,9 @@ impl Type {
 
     fn get_number(&self) -> f64 {
         match self {
+            Type::Integer(value) => value.to_f64(),
+            Type::Rational(value) => value.to_f64().unwrap_or(f64::NAN),
+            Type::Complex(value) => value.re,
             Type::Number(value) => *value,
             Type::String(value) | Type::Symbol(value) => value.trim().parse().unwrap_or_default(),
             Type::Bool(value) => {
@@ -837,23 +1248,33 @@ impl Type {
                     0.0
                 }
             }
-            Type::List(value) => value.first().unwrap_or(&Type::Null).get_number(),
+            Type::List(value) => value.front().unwrap_or(&Type::Null).get_number(),
             Type::Null => 0.0,
-            Type::Function(Function::UserDefined(value)) => value.len() as f64,
-            Type::Function(Function::Python(value, _))
-            | Type::Function(Function::Module(value)) => value.len() as f64,
-            Type::Function(Function::BuiltIn(_)) => 0.0,
-            Type::Expr(value) | Type::Block(value) => value.len() as f64,
+            Type::Function(Function::UserDefined(_, value)) => value.len() as f64,
+            Type::Function(Function::Compiled(value)) => value.clauses.len() as f64,
+            Type::Function(Function::Python(value, _)) => value.len() as f64,
+            Type::Function(Function::Module(value)) => value.len() as f64,
+            Type::Function(Function::BuiltIn(_) | Function::Control(_) | Function::Scoped(_)) => {
+                0.0
+            }
+            Type::Expr(value) => value.to_string().len() as f64,
+            Type::Block(value) => program_to_string(value).len() as f64,
             Type::Struct(i) => i.len() as f64,
+            Type::Dict(i) => i.len() as f64,
+            Type::Set(i) => i.len() as f64,
+            Type::Regex(value) => value.as_str().len() as f64,
         }
     }
 
     fn get_string(&self) -> String {
         match self {
-            Type::Number(value) => value.to_string(),
+            Type::Integer(value) => value.to_string(),
+            Type::Rational(value) => rational_to_string(value),
+            Type::Complex(value) => complex_to_string(value),
+            Type::Number(value) => float_to_string(*value),
             Type::String(value) | Type::Symbol(value) => value.to_string(),
             Type::Bool(value) => value.to_string(),
-            Type::Expr(value) => format!("({})", value),
+            Type::Expr(value) => value.to_string(),
             Type::List(value) => format!(
                 "[{}]",
                 value
@@ -866,7 +1287,13 @@ impl Type {
             Type::Function(Function::BuiltIn(function)) => {
                 format!("<Built-in function: {:?}>", function)
             }
-            Type::Function(Function::UserDefined(value)) => {
+            Type::Function(Function::Control(function)) => {
+                format!("<Built-in function: {:?}>", function)
+            }
+            Type::Function(Function::Scoped(function)) => {
+                format!("<Built-in function: {:?}>", function)
+            }
+            Type::Function(Function::UserDefined(_, value)) => {
                 format!(
                     "<User-defined function: ({})>",
                     value
@@ -880,12 +1307,27 @@ impl Type {
                         .join(" ")
                 )
             }
-            Type::Block(value) => format!("{{ {} }}", value),
+            Type::Function(Function::Compiled(value)) => {
+                format!(
+                    "<User-defined function: ({})>",
+                    value
+                        .clauses
+                        .iter()
+                        .last()
+                        .unwrap()
+                        .patterns
+                        .iter()
+                        .map(|i| i.get_symbol())
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                )
+            }
+            Type::Block(value) => format!("{{ {} }}", program_to_string(value)),
             Type::Function(Function::Python(value, _)) => {
                 format!("<Python function: {:x}>", value.as_ptr() as u8)
             }
             Type::Function(Function::Module(value)) => {
-                format!("<Module function: {:x}>", value.as_ptr() as u8)
+                format!("<Module function: {:x}>", Rc::as_ptr(value) as u8)
             }
             Type::Struct(value) => {
                 format!(
@@ -897,16 +1339,22 @@ impl Type {
                         .join("; ")
                 )
             }
+            Type::Dict(value) => dict_to_string(value),
+            Type::Set(value) => set_to_string(value),
+            Type::Regex(value) => value.as_str().to_string(),
         }
     }
 
     fn get_symbol(&self) -> String {
         match self {
-            Type::Number(value) => value.to_string(),
-            Type::String(value) => format!("\"{}\"", value),
+            Type::Integer(value) => value.to_string(),
+            Type::Rational(value) => rational_to_string(value),
+            Type::Complex(value) => complex_to_string(value),
+            Type::Number(value) => float_to_string(*value),
+            Type::String(value) => format!("\"{}\"", escape(value)),
             Type::Symbol(value) => value.to_string(),
             Type::Bool(value) => value.to_string(),
-            Type::Expr(value) => format!("({})", value),
+            Type::Expr(value) => value.to_string(),
             Type::List(value) => format!(
                 "[{}]",
                 value
@@ -919,7 +1367,13 @@ impl Type {
             Type::Function(Function::BuiltIn(function)) => {
                 format!("<Built-in function: {:?}>", function)
             }
-            Type::Function(Function::UserDefined(value)) => {
+            Type::Function(Function::Control(function)) => {
+                format!("<Built-in function: {:?}>", function)
+            }
+            Type::Function(Function::Scoped(function)) => {
+                format!("<Built-in function: {:?}>", function)
+            }
+            Type::Function(Function::UserDefined(_, value)) => {
                 format!(
                     "<User-defined function: ({})>",
                     value
@@ -933,12 +1387,27 @@ impl Type {
                         .join(" ")
                 )
             }
-            Type::Block(value) => format!("{{ {} }}", value),
+            Type::Function(Function::Compiled(value)) => {
+                format!(
+                    "<User-defined function: ({})>",
+                    value
+                        .clauses
+                        .iter()
+                        .last()
+                        .unwrap()
+                        .patterns
+                        .iter()
+                        .map(|i| i.get_symbol())
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                )
+            }
+            Type::Block(value) => format!("{{ {} }}", program_to_string(value)),
             Type::Function(Function::Python(value, _)) => {
                 format!("<Python function: {:x}>", value.as_ptr() as u8)
             }
             Type::Function(Function::Module(value)) => {
-                format!("<Module function: {:x}>", value.as_ptr() as u8)
+                format!("<Module function: {:x}>", Rc::as_ptr(value) as u8)
             }
             Type::Struct(value) => {
                 format!(
@@ -950,43 +1419,81 @@ impl Type {
                         .join("; ")
                 )
             }
+            Type::Dict(value) => dict_to_string(value),
+            Type::Set(value) => set_to_string(value),
+            Type::Regex(value) => format!("(regex \"{}\")", escape(value.as_str())),
         }
     }
 
     fn get_bool(&self) -> bool {
         match self {
+            Type::Integer(value) => !value.is_zero(),
+            Type::Rational(value) => !value.is_zero(),
+            Type::Complex(value) => !value.is_zero(),
             Type::Number(value) => *value != 0.0,
             Type::String(value) | Type::Symbol(value) => value.trim().parse().unwrap_or_default(),
             Type::Bool(value) => *value,
-            Type::List(value) => value.first().unwrap_or(&Type::Null).get_bool(),
+            Type::List(value) => value.front().unwrap_or(&Type::Null).get_bool(),
             Type::Null => false,
             Type::Function(_) => true,
-            Type::Expr(value) | Type::Block(value) => !value.is_empty(),
+            Type::Expr(value) => !matches!(&value.kind, NodeKind::Expr(terms) if terms.is_empty()),
+            Type::Block(value) => !value.is_empty(),
             Type::Struct(s) => !s.is_empty(),
+            Type::Dict(d) => !d.is_empty(),
+            Type::Set(s) => !s.is_empty(),
+            Type::Regex(r) => !r.as_str().is_empty(),
         }
     }
 
-    fn get_list(&self) -> Vec<Type> {
+    fn get_list(&self) -> Vector<Type> {
         match self {
             Type::List(value) => value.to_owned(),
+            Type::Dict(value) => value.iter().map(|(k, v)| dict::to_entry(k, v)).collect(),
+            Type::Set(value) => value.iter().map(Key::to_type).collect(),
             Type::String(value) => value.chars().map(|c| Type::String(c.to_string())).collect(),
-            other => vec![other.to_owned()],
+            other => vector![other.to_owned()],
         }
     }
 
-    fn get_struct(&self) -> HashMap<String, Type> {
+    fn get_function(&self) -> Result<Function, PravdaError> {
         match self {
-            Type::Struct(value) => value.to_owned(),
-            _ => HashMap::new(),
+            Type::Function(value) => Ok(value.to_owned()),
+            other => Err(type_error("function", other)),
+        }
+    }
+
+    /// Name of the type that's shown by `type` function
+    fn type_name(&self) -> &'static str {
+        match self {
+            Type::Integer(_) => "integer",
+            Type::Rational(_) => "rational",
+            Type::Complex(_) => "complex",
+            Type::Number(_) => "float",
+            Type::String(_) => "string",
+            Type::Bool(_) => "bool",
+            Type::List(_) => "list",
+            Type::Expr(_) => "expr",
+            Type::Block(_) => "block",
+            Type::Symbol(_) => "symbol",
+            Type::Function(_) => "function",
+            Type::Null => "null",
+            Type::Struct(_) => "struct",
+            Type::Dict(_) => "dict",
+            Type::Set(_) => "set",
+            Type::Regex(_) => "regex",
         }
     }
 
     fn to_pyobj(&self) -> String {
         match self {
-            Type::Number(value) => value.to_string(),
+            Type::Integer(value) => value.to_string(),
+            Type::Rational(value) => format!("({}/{})", value.numer(), value.denom()),
+            Type::Complex(value) => format!("complex({}, {})", value.re, value.im),
+            Type::Number(value) => float_to_string(*value),
             Type::String(value) => format!(
                 "\"{}\"",
                 value
+                    .replace("\\", "\\\\")
                     .replace("\n", "\\n")
                     .replace("\t", "\\t")
                     .replace("\"", &format!("\\{}", "\""))
@@ -1008,34 +1515,73 @@ impl Type {
                     .collect::<Vec<String>>()
                     .join(", ")
             ),
+            Type::Dict(value) => format!(
+                "{{{}}}",
+                value
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k.to_type().to_pyobj(), v.to_pyobj()))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Type::Set(value) if value.is_empty() => "set()".to_string(),
+            Type::Set(value) => format!(
+                "{{{}}}",
+                value
+                    .iter()
+                    .map(|i| i.to_type().to_pyobj())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
             Type::Null => "None".to_string(),
             _ => "()".to_string(),
         }
     }
 }
 
+/// Name of the user-defined function that's not bound yet
+const LAMBDA: &str = "<lambda>";
+
 /// Object of user-defined function
 type UserDefinedFunction = Vec<(
     Vec<Type>, // The argument pattern and become the key
     (
-        String,                // A program code of the function written in Pravda code
-        HashMap<String, Type>, // Memory of variables and functions to access in the calling
+        Rc<Node>, // A program code of the function written in Pravda code
+        Captured, // Scope where the function is defined, and arguments are bound in
     ), // Become the value
 )>;
 
+/// Object of built-in function
+type BuiltInFunction = fn(
+    Vec<Type>, // Passed arguments when it is calling
+    &Rc<Env>,  // Scope of variables and functions to access in the calling
+) -> Result<Type, PravdaError>;
+
+/// Object of built-in function that controls which code to run, such as `if`
+type ControlFunction = fn(
+    Vec<Type>, // Passed arguments when it is calling
+    &Rc<Env>,  // Scope of variables and functions to access in the calling
+) -> Result<Flow, PravdaError>;
+
 /// Function object used in the Pravda
 #[derive(Clone, Debug)]
 enum Function {
     /// Built-in function written in Rust code
-    BuiltIn(
-        fn(
-            Vec<Type>,             // Passed arguments when it is calling
-            HashMap<String, Type>, // Memory of variables and functions to access in the calling
-        ) -> Type,
-    ),
+    BuiltIn(BuiltInFunction),
+
+    /// Built-in function that leaves the code to evaluate to the evaluator
+    Control(ControlFunction),
+
+    /// Built-in function that reads or writes the variables of the caller, such as `reduce`
+    Scoped(BuiltInFunction),
 
     /// User-defined function written in Pravda code
-    UserDefined(UserDefinedFunction),
+    UserDefined(
+        String, // Name to show in the traceback
+        UserDefinedFunction,
+    ),
+
+    /// User-defined function that's compiled to bytecode
+    Compiled(Rc<vm::Closure>),
 
     /// Python library function
     Python(
@@ -1045,543 +1591,645 @@ enum Function {
 
     /// Pravda module function
     Module(
-        String, // Source code
+        Rc<Vec<Stmt>>, // Parsed program of the module
     ),
 }
 
+/// What the control function leaves to the evaluator
+enum Flow {
+    /// Result value of the calling
+    Value(Type),
+    /// Expression or block to evaluate in place of the calling
+    Eval(Type),
+    /// Variable to look up in the scope of the caller
+    Lookup(String),
+}
+
+/// Result of evaluating the code in tail position
+/// Calling user-defined function is left to the caller,
+/// so that tail call runs without growing the stack
+enum Tail {
+    Value(Type),
+    Call {
+        function: Function,
+        params: Vec<Type>,
+        span: Span,
+        /// How many times the result is called again without arguments, like `((f x))`
+        invoke: usize,
+    },
+}
+
+impl Tail {
+    /// Finish the calling that's left
+    fn resolve(self, memory: &Rc<Env>) -> Result<Type, PravdaError> {
+        match self {
+            Tail::Value(value) => Ok(value),
+            Tail::Call {
+                function,
+                params,
+                span,
+                invoke,
+            } => {
+                let result =
+                    call_function(function, params, memory).map_err(|err| err.called_at(&span))?;
+                invoke_result(result, invoke, memory)
+            }
+        }
+    }
+}
+
+/// Call the result again without arguments while it's function
+fn invoke_result(mut result: Type, times: usize, memory: &Rc<Env>) -> Result<Type, PravdaError> {
+    for _ in 0..times {
+        let Type::Function(func) = result else {
+            break;
+        };
+        result = call_function(func, vec![], memory)?;
+    }
+    Ok(result)
+}
+
+/// Parse the source code and run it as program
+/// # Arguments
+/// * `name` - File path or name of the input to show in diagnostics
+/// * `code` - The source code string to run as program
+/// * `memory` - Has functions and variables to access in the program
+/// # Return values
+/// This functions returns value that's result of running
+fn run_code(name: &str, code: &str, memory: &Rc<Env>) -> Result<Type, PravdaError> {
+    execute(&parse(name, code)?, memory)
+}
+
+/// Run the program by the backend that's chosen in the command-line
+fn execute(program: &[Stmt], memory: &Rc<Env>) -> Result<Type, PravdaError> {
+    match BACKEND.with(|backend| backend.get()) {
+        Backend::Tree => run_program(program, memory),
+        Backend::Vm => vm::run_program(program, memory),
+    }
+}
+
 /// Run the program and return result value
 /// # Arguments
-/// * `source` - The source code string to run as program
+/// * `program` - Lines of the parsed program to run
 /// * `memory` - Has functions and variables to access in the program
 /// # Return values
 /// This functions returns value that's result of running
-fn run_program(source: String, memory: &mut HashMap<String, Type>) -> Type {
-    let source = tokenize_program(source);
+fn run_program(program: &[Stmt], memory: &Rc<Env>) -> Result<Type, PravdaError> {
+    run_program_tail(program, memory)?.resolve(memory)
+}
+
+/// Run the program, and leave calling on the last line to the caller
+fn run_program_tail(program: &[Stmt], memory: &Rc<Env>) -> Result<Tail, PravdaError> {
     let mut result = Type::Null;
 
     // Execute each line
-    for lines in source {
-        if lines.len() == 2 {
-            let define = tokenize_expr(lines[0].to_string());
-            // Is the line includes `=` ?
-            if define.len() > 1 {
-                if let Some(Type::Function(Function::UserDefined(exist))) = memory.get(&define[0]) {
+    for (index, line) in program.iter().enumerate() {
+        match &line.kind {
+            StmtKind::Function(name, define, body) => {
+                // Prepare argument
+                let args = define
+                    .iter()
+                    .map(|i| eval_pattern(i, memory))
+                    .collect::<Result<Vec<Type>, PravdaError>>()?;
+                if let Some(Type::Function(Function::UserDefined(_, exist))) = memory.get(name) {
                     let mut exist = exist.clone();
-                    // Prepare argument
-                    let args: Vec<Type> = define[1..define.len()]
-                        .to_vec()
-                        .iter()
-                        .map(|i| eval_expr(i.to_string(), memory))
-                        .collect();
-                    if exist[0].0.len() == args.len() {
-                        // Add pattern match of the function
-                        exist.push((args, (lines[1].clone(), memory.to_owned())));
-                        let object = Type::Function(Function::UserDefined(exist));
-                        result = object.clone();
-                        memory.insert(define[0].to_string(), object);
-                    } else {
-                        eprintln!("Error! the function arguments length should be immutable");
+                    if exist[0].0.len() != args.len() {
+                        return Err(PravdaError::new(
+                            ErrorKind::Arity,
+                            "the function arguments length should be immutable",
+                        )
+                        .at(&line.span));
                     }
+                    // Add pattern match of the function
+                    exist.push((args, (body.clone(), Captured::Strong(memory.clone()))));
+                    let object = Type::Function(Function::UserDefined(name.to_string(), exist));
+                    result = object.clone();
+                    memory.define(name, object);
                 } else {
                     // Define new function
-                    let object = Type::Function(Function::UserDefined(vec![(
-                        define[1..define.len()]
-                            .to_vec()
-                            .iter()
-                            .map(|i| Type::parse(i.to_string(), memory))
-                            .collect(),
-                        (
-                            lines[1..lines.len()].to_vec().join(" = "),
-                            memory.to_owned(),
-                        ),
-                    )]));
+                    let object = Type::Function(Function::UserDefined(
+                        name.to_string(),
+                        vec![(args, (body.clone(), Captured::Strong(memory.clone())))],
+                    ));
                     result = object.clone();
-                    memory.insert(define[0].to_string(), object);
-                }
-            } else {
-                // Define variable
-                result = eval_expr(lines[1..lines.len()].to_vec().join(" = "), memory);
-                memory.insert(define[0].to_string(), result.clone());
-            }
-        } else {
-            // Evaluate the expression
-            result = eval_expr(lines[0].to_string(), memory);
-        }
-    }
-    result
-}
-
-/// # Tokenize for the expression
-/// return 2 length vector splitted by it if the line has `=` else just the line in the top vector
-/// ```
-/// let result = tokenize_expr("3; x = { a = 1; a }")
-/// assert_eq!(result, vec![vec!["3"], vec!["x", "{ a = 1; a }"]]);
-/// ```
-fn tokenize_program(input: String) -> Vec<Vec<String>> {
-    let mut tokens: Vec<Vec<String>> = Vec::new();
-    let mut current_token = String::new();
-    let mut after_equal = String::new();
-    let mut is_equal = false;
-    let mut in_parentheses: usize = 0;
-    let mut in_quote = false;
-
-    for c in input.chars() {
-        match c {
-            '{' if !in_quote => {
-                if is_equal {
-                    after_equal.push(c);
-                } else {
-                    current_token.push(c);
-                }
-                in_parentheses += 1;
-            }
-            '}' if !in_quote => {
-                if is_equal {
-                    after_equal.push(c);
-                } else {
-                    current_token.push(c);
-                }
-                in_parentheses -= 1;
-            }
-            ';' if !in_quote => {
-                if in_parentheses != 0 {
-                    if is_equal {
-                        after_equal.push(c);
-                    } else {
-                        current_token.push(c);
-                    }
-                } else if !current_token.is_empty() {
-                    if is_equal {
-                        is_equal = false;
-                        tokens.push(vec![current_token.clone(), after_equal.clone()]);
-                        current_token.clear();
-                        after_equal.clear();
-                    } else {
-                        tokens.push(vec![current_token.clone()]);
-                        current_token.clear();
-                    }
+                    memory.define(name, object);
                 }
             }
-            '=' if !in_quote => {
-                if in_parentheses != 0 {
-                    if is_equal {
-                        after_equal.push(c);
-                    } else {
-                        current_token.push(c);
+            StmtKind::Define(name, value) => {
+                // Define variable, and the lambda is named after it
+                result = match eval_expr(value, memory)? {
+                    Type::Function(Function::UserDefined(anonymous, object))
+                        if anonymous == LAMBDA =>
+                    {
+                        Type::Function(Function::UserDefined(name.to_string(), object))
                     }
-                } else {
-                    is_equal = true;
-                }
+                    value => value,
+                };
+                memory.define(name, result.clone());
             }
-            '"' => {
-                in_quote = !in_quote;
-                if is_equal {
-                    after_equal.push(c);
-                } else {
-                    current_token.push(c);
-                }
+            StmtKind::Expr(expr) if index + 1 == program.len() => {
+                return eval_tail(expr, memory);
             }
-            _ => {
-                if is_equal {
-                    after_equal.push(c);
-                } else {
-                    current_token.push(c);
-                }
+            StmtKind::Expr(expr) => {
+                // Evaluate the expression
+                result = eval_expr(expr, memory)?;
             }
         }
     }
-
-    if in_parentheses == 0 && !current_token.is_empty() {
-        if is_equal {
-            tokens.push(vec![current_token.clone(), after_equal]);
-            current_token.clear();
-        } else {
-            tokens.push(vec![current_token.clone()]);
-            current_token.clear();
-        }
-    }
-    tokens
+    Ok(Tail::Value(result))
 }
 
 /// Evaluate the expression and return result value
 /// # Arguments
-/// * `expr` - The expression string to evaluate
+/// * `expr` - The expression node to evaluate
 /// * `memory` - Has functions and variables to access in the expression
 /// # Return values
 /// This functions returns value that's result of evaluating
-fn eval_expr(expr: String, memory: &HashMap<String, Type>) -> Type {
-    // Parse expression
-    let expr: Vec<Type> = tokenize_expr(expr)
-        .iter()
-        .map(|i| Type::parse(i.to_owned(), memory))
-        .collect();
-
-    if expr.is_empty() {
-        return Type::Null;
-    }
+fn eval_expr(expr: &Node, memory: &Rc<Env>) -> Result<Type, PravdaError> {
+    eval_tail(expr, memory)?.resolve(memory)
+}
 
-    if let Type::Symbol(identify) = expr[0].clone() {
-        if let Some(value) = memory.get(&identify) {
-            // Read memory value
-            if let Type::Function(name) = value {
-                call_function(name.to_owned(), expr[1..expr.len()].to_vec(), memory)
-            } else {
-                value.to_owned()
+/// Evaluate the expression, and leave calling user-defined function to the caller
+fn eval_tail(expr: &Node, memory: &Rc<Env>) -> Result<Tail, PravdaError> {
+    let NodeKind::Expr(terms) = &expr.kind else {
+        return Ok(Tail::Value(eval_term(expr, memory)?));
+    };
+    let Some(head) = terms.first() else {
+        return Ok(Tail::Value(Type::Null));
+    };
+    let args = &terms[1..terms.len()];
+
+    // Call the function, and record the location if it fails
+    let call = |func: Function| {
+        let params = eval_args(args, memory)?;
+        let located = |err: PravdaError| err.called_at(&expr.span);
+        match func {
+            Function::UserDefined(..) => Ok(Tail::Call {
+                function: func,
+                params,
+                span: expr.span.clone(),
+                invoke: 0,
+            }),
+            Function::Control(control) => match control(params, memory).map_err(located)? {
+                Flow::Value(value) => Ok(Tail::Value(value)),
+                Flow::Eval(code) => eval_code(&code, memory),
+                Flow::Lookup(name) => Ok(Tail::Value(memory.get(&name).unwrap_or(Type::Null))),
+            },
+            Function::Scoped(_) => {
+                let result = call_function(func, params, memory);
+                warn_at(&expr.span);
+                Ok(Tail::Value(result.map_err(located)?))
             }
-        } else if Path::new(&identify.clone()).exists()
-            || home_dir()
-                .unwrap()
-                .join(Path::new(&identify.clone()))
-                .exists()
-        {
-            let result = run_program(format!("load {identify}"), &mut memory.clone());
-            if let Type::Function(func) = result {
-                call_function(func, expr[1..expr.len()].to_vec(), memory)
+            func => Ok(Tail::Value(
+                call_function(func, params, memory).map_err(located)?,
+            )),
+        }
+    };
+
+    match &head.kind {
+        NodeKind::Symbol(identify) => {
+            if let Some(value) = memory.get(identify) {
+                // Read memory value
+                if let Type::Function(name) = value {
+                    call(name.to_owned())
+                } else {
+                    Ok(Tail::Value(value.to_owned()))
+                }
+            } else if let Some(result) = load_path(identify, &head.span, memory)? {
+                match result {
+                    Type::Function(func) => call(func),
+                    result => Ok(Tail::Value(result)),
+                }
             } else {
-                result
+                Ok(Tail::Value(Type::Symbol(identify.to_string())))
             }
-        } else {
-            expr[0].clone()
         }
-    } else if let Type::Function(liberal) = &expr[0] {
-        call_function(liberal.clone(), expr[1..expr.len()].to_vec(), memory)
-    } else if let Type::Block(block) = &expr[0] {
-        // Evaluate the code block
-        let result = run_program(block.to_owned(), &mut memory.clone());
-        if let Type::Function(func) = result {
-            // If it's function, call it
-            call_function(func, expr[1..expr.len()].to_vec(), &memory.clone())
-        } else {
-            result
-        }
-    } else if let Type::Expr(code) = &expr[0] {
-        // Evaluate the expression
-        let result = eval_expr(code.to_owned(), &memory.clone());
-        if let Type::Function(func) = result {
-            // If it's function, call it
-            call_function(func, expr[1..expr.len()].to_vec(), &memory.clone())
-        } else {
-            result
+        NodeKind::Expr(_) if args.is_empty() => match eval_tail(head, memory)? {
+            // The result of the calling is called again after it's finished
+            Tail::Call {
+                function,
+                params,
+                span,
+                invoke,
+            } => Ok(Tail::Call {
+                function,
+                params,
+                span,
+                invoke: invoke + 1,
+            }),
+            Tail::Value(Type::Function(func)) => call(func),
+            result => Ok(result),
+        },
+        NodeKind::Lambda(..) | NodeKind::Block(_) | NodeKind::Expr(_) => {
+            // Evaluate the code, and if it's function, call it
+            match eval_term(head, memory)? {
+                Type::Function(func) => call(func),
+                result => Ok(Tail::Value(result)),
+            }
         }
-    } else if expr.len() == 1 {
-        expr[0].to_owned()
-    } else {
+        _ if args.is_empty() => Ok(Tail::Value(eval_term(head, memory)?)),
         // If there's multiple value, return it as a list
-        Type::List(expr)
+        _ => Ok(Tail::Value(Type::List(eval_args(terms, memory)?.into()))),
     }
 }
 
-/// Tokenize for the expression
-/// ```
-/// let result = tokenize_expr("+ 1 2 (* 3 4)")
-/// assert_eq!(result, vec!["+", "1", "2", "(* 3 4)"]);
-/// ```
-fn tokenize_expr(input: String) -> Vec<String> {
-    let mut tokens = Vec::new();
-    let mut current_token = String::new();
-    let mut in_parentheses: usize = 0;
-    let mut in_quote = false;
-
-    for c in input.chars() {
-        match c {
-            '(' if !in_quote => {
-                in_parentheses += 1;
-                current_token.push(c);
-            }
-            ')' if !in_quote => {
-                if in_parentheses != 0 {
-                    current_token.push(c);
-                    in_parentheses -= 1;
-                    if in_parentheses == 0 {
-                        tokens.push(current_token.clone());
-                        current_token.clear();
-                    }
-                }
-            }
-            '[' if !in_quote => {
-                in_parentheses += 1;
-                current_token.push(c);
-            }
-            ']' if !in_quote => {
-                if in_parentheses != 0 {
-                    current_token.push(c);
-                    in_parentheses -= 1;
-                    if in_parentheses == 0 {
-                        tokens.push(current_token.clone());
-                        current_token.clear();
-                    }
-                }
-            }
-            '{' if !in_quote => {
-                in_parentheses += 1;
-                current_token.push(c);
-            }
-            '}' if !in_quote => {
-                if in_parentheses != 0 {
-                    current_token.push(c);
-                    in_parentheses -= 1;
-                    if in_parentheses == 0 {
-                        tokens.push(current_token.clone());
-                        current_token.clear();
-                    }
-                }
+/// Load the file by `load` function if the name is path of it
+/// # Arguments
+/// * `path` - Name that's called as function
+/// * `span` - Where the name is written
+/// * `memory` - Has functions and variables to access in the calling
+/// # Return values
+/// This functions returns the loaded value, or none if there's no such file
+fn load_path(path: &str, span: &Span, memory: &Rc<Env>) -> Result<Option<Type>, PravdaError> {
+    if !(Path::new(path).exists()
+        || home_dir().is_some_and(|home| home.join(Path::new(path)).exists()))
+    {
+        return Ok(None);
+    }
+    let node = |kind| Node {
+        kind,
+        span: span.clone(),
+    };
+    let load = node(NodeKind::Expr(vec![
+        node(NodeKind::Symbol("load".to_string())),
+        node(NodeKind::String(path.to_string())),
+    ]));
+    eval_expr(&load, memory).map(Some)
+}
+
+/// Evaluate the code that's left by the control function
+/// Expression is evaluated in the current scope, and block is run in the new one
+fn eval_code(code: &Type, memory: &Rc<Env>) -> Result<Tail, PravdaError> {
+    match code {
+        Type::Expr(expr) => eval_tail(expr, memory),
+        Type::Block(program) => run_program_tail(program, &Env::child(memory)),
+        other => Ok(Tail::Value(other.clone())),
+    }
+}
+
+/// Evaluate the term that's element of the expression
+fn eval_term(term: &Node, memory: &Rc<Env>) -> Result<Type, PravdaError> {
+    Ok(match &term.kind {
+        NodeKind::Integer(value) => Type::Integer(value.clone()),
+        NodeKind::Rational(value) => number::from_ratio(value.clone()),
+        NodeKind::Imaginary(value) => Type::Complex(Complex64::new(0.0, *value)),
+        NodeKind::Number(value) => Type::Number(*value),
+        NodeKind::String(value) => Type::String(value.to_string()),
+        NodeKind::Bool(value) => Type::Bool(*value),
+        NodeKind::Null => Type::Null,
+        NodeKind::Symbol(name) => memory
+            .get(name)
+            .unwrap_or_else(|| Type::Symbol(name.to_string())),
+        NodeKind::Expr(_) => eval_expr(term, memory)?,
+        NodeKind::Block(program) => run_program(program, &Env::child(memory))?,
+        NodeKind::List(items) => Type::List(eval_args(items, memory)?.into()),
+        NodeKind::Lambda(define, body) => Type::Function(Function::UserDefined(
+            LAMBDA.to_string(),
+            vec![(
+                define
+                    .iter()
+                    .map(|i| eval_pattern(i, memory))
+                    .collect::<Result<Vec<Type>, PravdaError>>()?,
+                (body.clone(), Captured::Strong(memory.clone())),
+            )],
+        )),
+        NodeKind::Struct(fields) => Type::Struct(
+            fields
+                .iter()
+                .map(|(name, value)| Ok((name.to_string(), eval_expr(value, memory)?)))
+                .collect::<Result<ImHashMap<String, Type>, PravdaError>>()?,
+        ),
+        NodeKind::Dict(entries) => {
+            let mut dict = Dict::new();
+            for (key, value) in entries {
+                let key = Key::new(&eval_expr(key, memory)?).map_err(|err| err.at(&key.span))?;
+                dict.insert(key, eval_expr(value, memory)?);
             }
-            '"' => {
-                if in_parentheses == 0 {
-                    if in_quote {
-                        current_token.push(c);
-                        in_quote = false;
-                        tokens.push(current_token.clone());
-                        current_token.clear();
-                    } else {
-                        in_quote = true;
-                        current_token.push(c);
+            Type::Dict(dict)
+        }
+        NodeKind::Set(items) => {
+            let set = to_set(eval_args(items, memory)?).map_err(|err| err.at(&term.span))?;
+            Type::Set(set)
+        }
+        NodeKind::Lazy(term) => match &term.kind {
+            // Processing of lazy evaluate expression
+            NodeKind::Expr(_) => Type::Expr(term.clone()),
+            NodeKind::Block(program) => Type::Block(program.clone()),
+            NodeKind::Symbol(name) => Type::Symbol(name.to_string()),
+            _ => eval_term(term, memory)?,
+        },
+        NodeKind::Spread(term) => eval_term(term, memory)?,
+        NodeKind::Format(parts) => {
+            let mut text = String::new();
+            for part in parts {
+                match part {
+                    Part::Text(value) => text.push_str(value),
+                    Part::Value(expr, spec) => {
+                        let value = eval_expr(expr, memory)?;
+                        let value = format_value(&value, spec.as_ref())
+                            .map_err(|err| err.at(&expr.span))?;
+                        text.push_str(&value);
                     }
-                } else {
-                    current_token.push(c);
-                }
-            }
-            ' ' | '\n' | '\t' | '\r' | '　' => {
-                if in_parentheses != 0 || in_quote {
-                    current_token.push(c);
-                } else if !current_token.is_empty() {
-                    tokens.push(current_token.clone());
-                    current_token.clear();
                 }
             }
-            _ => {
-                current_token.push(c);
-            }
+            Type::String(text)
+        }
+    })
+}
+
+/// Evaluate the arguments of function calling
+fn eval_args(terms: &[Node], memory: &Rc<Env>) -> Result<Vec<Type>, PravdaError> {
+    let mut params: Vec<Type> = vec![];
+    for term in terms {
+        if let NodeKind::Spread(term) = &term.kind {
+            // Expand the list as argument
+            params.extend(eval_term(term, memory)?.get_list());
+        } else {
+            params.push(eval_term(term, memory)?);
         }
     }
+    Ok(params)
+}
 
-    if !(in_parentheses != 0 || in_quote || current_token.is_empty()) {
-        tokens.push(current_token);
+/// Convert the argument definition to pattern of the function
+/// Symbol becomes variable to bind, and other value becomes pattern to match
+fn eval_pattern(define: &Node, memory: &Rc<Env>) -> Result<Type, PravdaError> {
+    match &define.kind {
+        NodeKind::Symbol(name) => Ok(Type::Symbol(name.to_string())),
+        // Mutable length argument
+        NodeKind::Spread(term) => Ok(Type::Symbol(format!("~{term}"))),
+        _ => eval_term(define, memory),
+    }
+}
+
+thread_local! {
+    /// Current depth of the function calling
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// Maximum depth of the function calling
+    static LIMIT: Cell<usize> = const { Cell::new(RECURSION_LIMIT) };
+    /// Evaluator that runs the program
+    static BACKEND: Cell<Backend> = const { Cell::new(Backend::Tree) };
+    /// Whether the deprecation of `reduce` has been warned
+    static REDUCE_WARNED: Cell<bool> = const { Cell::new(false) };
+    /// Warning that the built-in function left to show where it's called
+    static WARNING: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Show the warning that the built-in function left, at the location of the calling
+fn warn_at(span: &Span) {
+    if let Some(message) = WARNING.take() {
+        diagnostic::warn(&message, span);
+    }
+}
+
+/// Guard that counts the depth while the function is running
+struct Depth;
+
+impl Depth {
+    /// Go deeper to call the function, or error if it's over the limit
+    fn enter(function: &Function) -> Result<Depth, PravdaError> {
+        let depth = DEPTH.with(|depth| depth.get()) + 1;
+        let limit = LIMIT.with(|limit| limit.get());
+        if depth > limit {
+            let name = match function {
+                Function::UserDefined(name, _) => name.as_str(),
+                Function::Compiled(closure) => closure.name.as_str(),
+                Function::Python(..) => "<python>",
+                Function::Module(_) => "<module>",
+                Function::BuiltIn(_) | Function::Control(_) | Function::Scoped(_) => "<built-in>",
+            };
+            return Err(PravdaError::new(
+                ErrorKind::Recursion,
+                format!("maximum recursion depth exceeded while calling `{name}`"),
+            )
+            .with("function", Type::String(name.to_string()))
+            .with("limit", Type::Integer(Int::from(limit))));
+        }
+        DEPTH.with(|cell| cell.set(depth));
+        Ok(Depth)
+    }
+}
+
+impl Drop for Depth {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
     }
-    tokens
 }
 
 /// Call ordered function and return result value
 /// # Arguments
 /// * `function` - The function object to call
-/// * `args` - Several arguments that will be passed to function
+/// * `params` - Several arguments that will be passed to function
 /// * `memory` - Has functions and variables to access in the calling
 /// # Return values
 /// This functions returns value that's result of calling
-fn call_function(function: Function, args: Vec<Type>, memory: &HashMap<String, Type>) -> Type {
-    let mut params: Vec<Type> = vec![];
-    for i in args {
-        // Prepare arguments
-        if let Type::Expr(code) = i.clone() {
-            let value = eval_expr(code, &memory.clone());
-            if value.get_symbol().starts_with("@") {
-                // Processing of lazy evaluate variable
-                let value = Type::parse(
-                    value.get_symbol()[1..value.get_symbol().len()].to_string(),
-                    memory,
-                );
-                params.push(value)
-            } else if value.get_symbol().starts_with("lazy") {
-                // Processing of lazy evaluate variable
-                let value = Type::parse(
-                    value.get_symbol()["lazy".len()..value.get_symbol().len()].to_string(),
-                    memory,
-                );
-                params.push(value)
-            } else {
-                params.push(value.to_owned())
-            }
-        } else if let Type::Block(block) = i.clone() {
-            let value = run_program(block, &mut memory.clone());
-            if value.get_symbol().starts_with("@") {
-                // Processing of lazy evaluate variable
-                let value = Type::parse(
-                    value.get_symbol()[1..value.get_symbol().len()].to_string(),
-                    memory,
-                );
-                params.push(value)
-            } else if value.get_symbol().starts_with("lazy") {
-                // Processing of lazy evaluate variable
-                let value = Type::parse(
-                    value.get_symbol()["lazy".len()..value.get_symbol().len()].to_string(),
-                    memory,
-                );
-                params.push(value)
-            } else {
-                params.push(value.to_owned())
+fn call_function(
+    mut function: Function,
+    mut params: Vec<Type>,
+    memory: &Rc<Env>,
+) -> Result<Type, PravdaError> {
+    // Built-in function isn't counted, since it's not recursive by itself
+    let _depth = match function {
+        Function::BuiltIn(_) | Function::Control(_) | Function::Scoped(_) => None,
+        _ => Some(Depth::enter(&function)?),
+    };
+
+    // Tail call of user-defined function continues this loop
+    let mut invoke = 0;
+    let result = loop {
+        let (name, object) = match function {
+            Function::BuiltIn(function) | Function::Scoped(function) => {
+                break function(params, memory)
             }
-        } else if let Type::Symbol(name) = i.clone() {
-            if name.starts_with("~") {
-                // Processing of mutable length argument
-                let name = name[1..name.len()].to_string();
-                let value = Type::parse(name.clone(), memory);
-                if let Some(value) = memory.get(&name) {
-                    for j in value.get_list() {
-                        // Expand　the list as argument
-                        params.push(j.to_owned())
-                    }
-                } else if let Type::List(list) = value {
-                    for j in list {
-                        // Expand　the list as argument
-                        params.push(j.to_owned())
-                    }
-                } else if let Type::Expr(code) = value {
-                    let result = eval_expr(code, &memory.clone());
-                    for j in result.get_list() {
-                        // Expand　the list as argument
-                        params.push(j.to_owned())
-                    }
-                } else if let Type::Block(code) = value {
-                    // Run the code
-                    let result = run_program(code, &mut memory.clone());
-                    for j in result.get_list() {
-                        // Expand　the list as argument
-                        params.push(j.to_owned())
-                    }
-                } else {
-                    params.push(value)
-                }
-            } else if name.starts_with("@") {
-                // Processing of lazy evaluate expression
-                params.push(Type::parse(name[1..name.len()].to_string(), memory))
-            } else if name.starts_with("lazy") {
-                // Processing of lazy evaluate expression
-                params.push(Type::parse(
-                    name["lazy".len()..name.len()].to_string(),
-                    memory,
-                ))
-            } else if let Some(value) = memory.get(&name) {
-                if value.get_symbol().starts_with("@") {
-                    // Processing of lazy evaluate variable
-                    let value = Type::parse(
-                        value.get_symbol()[1..value.get_symbol().len()].to_string(),
-                        memory,
-                    );
-                    params.push(value)
-                } else if value.get_symbol().starts_with("lazy") {
-                    // Processing of lazy evaluate variable
-                    let value = Type::parse(
-                        value.get_symbol()["lazy".len()..value.get_symbol().len()].to_string(),
-                        memory,
-                    );
-                    params.push(value)
-                } else {
-                    params.push(value.to_owned())
+            Function::Control(function) => {
+                break match function(params, memory)? {
+                    Flow::Value(value) => Ok(value),
+                    Flow::Eval(code) => eval_code(&code, memory)?.resolve(memory),
+                    Flow::Lookup(name) => Ok(memory.get(&name).unwrap_or(Type::Null)),
                 }
-            } else {
-                params.push(i.to_owned())
             }
-        } else {
-            params.push(i.to_owned());
-        }
-    }
+            Function::Compiled(closure) => break vm::call(&closure, params),
+            Function::Python(code, depend) => break call_python(code, params, depend),
+            Function::Module(program) => {
+                let module = match program.first() {
+                    Some(line) => format!("<module {}>", line.span.source.name),
+                    None => "<module>".to_string(),
+                };
+                // The module can't access the variables of the caller
+                break match execute(&program, &Env::child(&memory.root())) {
+                    Ok(Type::Function(func)) => call_function(func, params, memory)
+                        .map_err(|err| err.in_frame(module, None)),
+                    Ok(result) => Ok(result),
+                    Err(err) => Err(err.in_frame(module, None)),
+                };
+            }
+            Function::UserDefined(name, object) => (name, object),
+        };
 
-    if let Function::BuiltIn(function) = function {
-        function(params, memory.to_owned())
-    } else if let Function::UserDefined(object) = function {
-        if let Some((program, scope)) = {
-            let mut temp = None;
-            for item in object.clone() {
-                if item
-                    .0
-                    .iter()
-                    .map(|i| i.get_symbol())
-                    .collect::<Vec<String>>()
-                    .join("\n")
-                    == params
+        let index = match_clause(object.iter().map(|(args, _)| args.as_slice()), &params)?;
+        let (args, (program, scope)) = &object[index];
+
+        // Arguments are bound in the new scope inside where the function is defined
+        let scope = &Env::child(&scope.scope());
+        for (index, arg) in args.iter().enumerate() {
+            if let Type::Symbol(name) = arg {
+                if let Some(name) = name.strip_prefix('~') {
+                    // Processing of mutable length argument
+                    let rest = params[index.min(params.len())..params.len()]
                         .iter()
-                        .map(|i| i.get_symbol())
-                        .collect::<Vec<String>>()
-                        .join("\n")
-                {
-                    temp = Some(item.1);
+                        .cloned();
+                    scope.define(name, Type::List(rest.collect()));
+                } else if let Some(value) = params.get(index) {
+                    // Set argument value as variable
+                    scope.define(name, value.to_owned());
                 }
             }
-            temp
-        } {
-            // Matched pattern
-            let scope = scope.clone();
-            eval_expr(program.to_string(), &scope)
-        } else if let Some((args, (program, scope))) = {
-            // Normal argument, not pattern
-            let mut flag = None;
-            for item in {
-                let mut object = object.clone();
-                object.reverse();
-                object
-            } {
-                if item.0.iter().all(|i| matches!(i, Type::Symbol(_))) {
-                    flag = Some(item);
-                    break;
+        }
+
+        if required_count(args) <= params.len() {
+            // Execute function code
+            let tail = run_body(program, scope)
+                .map_err(|err| err.in_frame(&name, clause_label(&name, args, object.len())))?;
+            match tail {
+                Tail::Value(value) => break Ok(value),
+                Tail::Call {
+                    function: next,
+                    params: args,
+                    invoke: times,
+                    ..
+                } => {
+                    function = next;
+                    params = args;
+                    invoke += times;
                 }
             }
-            flag
-        } {
-            let scope: &mut HashMap<String, Type> = &mut scope.clone();
-            scope.extend(memory.to_owned()); // Update memory
-            if args[args.len() - 1].get_symbol().starts_with("~") {
-                for (arg, value) in args.iter().zip(params.to_vec()) {
-                    // Processing of mutable length argument
-                    if arg.get_symbol().starts_with("~") {
-                        scope.insert(
-                            arg.get_symbol()[1..arg.get_symbol().len()].to_string(),
-                            Type::List(
-                                params[params
-                                    .iter()
-                                    .position(|i| i.get_symbol() == value.get_symbol())
-                                    .unwrap()..params.len()]
-                                    .to_vec(),
-                            ),
-                        );
-                    } else {
-                        // Set argument value as variable
-                        scope.insert(arg.get_symbol(), value);
-                    }
-                }
+        } else if params.is_empty() {
+            // Calling without arguments gives the function itself
+            break Ok(Type::Function(Function::UserDefined(name, object.clone())));
+        } else {
+            // Partial application of the function
+            let mut object = object.clone();
+            object.push((
+                args[params.len()..args.len()].to_vec(),
+                (program.clone(), Captured::Strong(scope.to_owned())),
+            ));
+            break Ok(Type::Function(Function::UserDefined(name, object)));
+        }
+    };
+    invoke_result(result?, invoke, memory)
+}
+
+/// Choose the clause that matches most patterns, and the later one wins on a tie
+/// # Arguments
+/// * `clauses` - Argument patterns of each clause
+/// * `params` - Several arguments that will be passed to function
+/// # Return values
+/// This functions returns index of the chosen clause
+fn match_clause<'a>(
+    clauses: impl Iterator<Item = &'a [Type]>,
+    params: &[Type],
+) -> Result<usize, PravdaError> {
+    let mut matched: Option<(usize, usize)> = None;
+    for (index, args) in clauses.enumerate() {
+        let mut score = 0;
+        let is_match = args.iter().zip(params.iter()).all(|(arg, value)| {
+            if let Type::Symbol(_) = arg {
+                true
             } else {
-                for (arg, value) in args.iter().zip(params.to_vec()) {
-                    // Set argument value as variable
-                    scope.insert(arg.get_symbol(), value);
-                }
+                score += 1;
+                arg == value
             }
+        });
+        if is_match && matched.is_none_or(|(best, _)| score >= best) {
+            matched = Some((score, index));
+        }
+    }
+    match matched {
+        Some((_, index)) => Ok(index),
+        None => Err(PravdaError::new(
+            ErrorKind::Match,
+            format!(
+                "no clause of the function matches the arguments ({})",
+                params
+                    .iter()
+                    .map(|i| i.get_symbol())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+        )),
+    }
+}
 
-            if args.len() <= params.len() {
-                // Execute function code
-                if let Type::Block(block) = Type::parse(program.clone(), memory) {
-                    run_program(block, scope)
-                } else {
-                    eval_expr(program.to_string(), scope)
-                }
+/// Count the arguments that are required to run the function, except mutable length one
+fn required_count(args: &[Type]) -> usize {
+    args.iter()
+        .filter(|i| !i.get_symbol().starts_with('~'))
+        .count()
+}
+
+/// Show which clause is chosen in the traceback, if there's several ones
+fn clause_label(name: &str, args: &[Type], clauses: usize) -> Option<String> {
+    (clauses > 1).then(|| {
+        let patterns = args.iter().map(|i| i.get_symbol());
+        [name.to_string()]
+            .into_iter()
+            .chain(patterns)
+            .collect::<Vec<String>>()
+            .join(" ")
+    })
+}
+
+/// Run the body of user-defined function
+/// The code block is run in the scope of the function, not in the new one
+fn run_body(body: &Node, scope: &Rc<Env>) -> Result<Tail, PravdaError> {
+    match &body.kind {
+        NodeKind::Expr(terms) if terms.len() == 1 => {
+            if let NodeKind::Block(program) = &terms[0].kind {
+                run_program_tail(program, scope)
             } else {
-                // Partial application of the function
-                let mut object = object.clone();
-                object.push((
-                    args[params.len()..args.len()].to_vec(),
-                    (program.clone(), scope.to_owned()),
-                ));
-                Type::Function(Function::UserDefined(object))
+                eval_tail(body, scope)
             }
-        } else {
-            Type::Null
         }
-    } else if let Function::Python(code, depend) = function {
-        call_python(code, params, depend).unwrap_or(Type::Null)
-    } else if let Function::Module(code) = function {
-        let result = run_program(code, &mut memory.clone());
-        if let Type::Function(func) = result {
-            call_function(func, params, &memory.clone())
-        } else {
-            result.clone()
-        }
-    } else {
-        return Type::Null;
+        _ => eval_tail(body, scope),
     }
 }
 
 /// Calling function of the Python
-fn call_python(code: String, args: Vec<Type>, depend: Vec<String>) -> Option<Type> {
+fn call_python(code: String, args: Vec<Type>, depend: Vec<String>) -> Result<Type, PravdaError> {
     pyo3::prepare_freethreaded_python();
     Python::with_gil(|py| {
+        // Convert the Python exception to Pravda error, and its traceback to frames
+        let python_error = |err: PyErr| {
+            let exception = err.get_type(py).name().unwrap_or("Exception").to_string();
+            let mut error =
+                PravdaError::new(ErrorKind::Python, format!("{exception}: {}", err.value(py)))
+                    .with("exception", Type::String(exception));
+
+            let mut frames = Vec::new();
+            let mut traceback: Option<&PyAny> = err.traceback(py).map(|i| i.as_ref());
+            while let Some(current) = traceback.filter(|i| !i.is_none()) {
+                let function = current
+                    .getattr("tb_frame")
+                    .and_then(|i| i.getattr("f_code"))
+                    .and_then(|i| i.getattr("co_name"))
+                    .and_then(|i| i.extract::<String>());
+                let line = current
+                    .getattr("tb_lineno")
+                    .and_then(|i| i.extract::<usize>());
+                if let (Ok(function), Ok(line)) = (function, line) {
+                    // The code is wrapped by one line, so the number is shifted
+                    if function != "<module>" {
+                        frames.push(format!("{function} (Python line {})", line - 1));
+                    }
+                }
+                traceback = current.getattr("tb_next").ok();
+            }
+            for frame in frames.into_iter().rev() {
+                error = error.in_frame(frame, None);
+            }
+            error
+        };
+
         let context = PyDict::new(py);
         let code = format!(
             "
@@ -1596,20 +2244,15 @@ result = main({})
         );
 
         for lib in depend {
-            let module = if let Ok(module) = py.import(lib.as_str()) {
-                module
-            } else {
-                return None;
-            };
-            let Ok(_) = context.set_item(lib, module) else {
-                return None;
-            };
+            let module = py.import(lib.as_str()).map_err(python_error)?;
+            context.set_item(lib, module).map_err(python_error)?;
         }
 
-        if py.run(&code, Some(context), Some(context)).is_err() {
-            return None;
-        };
-        let result = context.get_item("result").unwrap();
-        Some(Type::from_python(result))
+        py.run(&code, Some(context), Some(context))
+            .map_err(python_error)?;
+        match context.get_item("result") {
+            Some(result) => Ok(Type::from_python(result)),
+            None => Ok(Type::Null),
+        }
     })
 }
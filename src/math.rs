@@ -0,0 +1,355 @@
+//! Built-in functions and constants of the mathematics
+//!
+//! The transcendental functions return float for the real number, and complex for the complex one.
+//! It's NaN if the real number is out of the domain, e.g. `sqrt -1`,
+//! so pass the complex number like `(+ -1 0i)` to get the complex result
+use crate::error::{ErrorKind, PravdaError};
+use crate::number::{self, Int};
+use crate::{check_arity, Function, Type};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{Signed, Zero};
+use std::cmp::Ordering;
+use std::f64::consts::{E, PI};
+
+/// Apply the function to the real or complex number
+fn unary(
+    name: &str,
+    params: &[Type],
+    real: fn(f64) -> f64,
+    complex: fn(Complex64) -> Complex64,
+) -> Result<Type, PravdaError> {
+    check_arity(name, params, 1)?;
+    match &params[0] {
+        Type::Complex(value) => Ok(Type::Complex(complex(*value))),
+        other => Ok(Type::Number(real(number::float(other)?))),
+    }
+}
+
+/// Round the real number to the digits after the decimal point, that's 0 by default
+/// The exact number is rounded exactly, and the float is rounded as float
+fn rounding(
+    name: &str,
+    params: &[Type],
+    exact: fn(&BigRational) -> BigRational,
+    real: fn(f64) -> f64,
+) -> Result<Type, PravdaError> {
+    check_arity(name, params, 1)?;
+    let digits = match params.get(1) {
+        Some(digits) => number::integer(digits)?
+            .to_i64()
+            .and_then(|i| i32::try_from(i).ok())
+            .ok_or_else(|| {
+                PravdaError::new(
+                    ErrorKind::Value,
+                    format!("the digits {} is too large", digits.get_symbol()),
+                )
+            })?,
+        None => 0,
+    };
+    match &params[0] {
+        Type::Integer(_) | Type::Rational(_) => {
+            // The scale is limited as well as the power, since it's exact
+            let scale = number::pow(
+                &Type::Integer(Int::Small(10)),
+                &Type::Integer(Int::Small(digits as i64)),
+            )
+            .map_err(|_| {
+                PravdaError::new(
+                    ErrorKind::Value,
+                    format!("the digits {digits} is too large"),
+                )
+            })?;
+            let scale = number::ratio(&scale)?;
+            let value = number::ratio(&params[0])? * &scale;
+            Ok(number::from_ratio(exact(&value) / scale))
+        }
+        other => {
+            let value = number::float(other)?;
+            if digits == 0 {
+                return Ok(Type::Number(real(value)));
+            }
+            // The float has at most 17 significant digits, so more digits keep it as it is
+            let scale = 10f64.powi(digits);
+            if !scale.is_finite() || digits >= 17 {
+                return Ok(Type::Number(value));
+            }
+            if scale == 0.0 {
+                return Ok(Type::Number(0f64.copysign(value)));
+            }
+            Ok(Type::Number(real(value * scale) / scale))
+        }
+    }
+}
+
+/// Get the integers of the arguments, or the list if it's only one
+fn integers(name: &str, params: &[Type]) -> Result<Vec<BigInt>, PravdaError> {
+    check_arity(name, params, 1)?;
+    let items: Vec<Type> = match params {
+        [Type::List(items)] => items.iter().cloned().collect(),
+        _ => params.to_vec(),
+    };
+    items
+        .iter()
+        .map(|item| Ok(number::integer(item)?.to_bigint()))
+        .collect()
+}
+
+pub fn builtins() -> Vec<(String, Type)> {
+    vec![
+        ("pi".to_string(), Type::Number(PI)),
+        ("e".to_string(), Type::Number(E)),
+        ("inf".to_string(), Type::Number(f64::INFINITY)),
+        ("nan".to_string(), Type::Number(f64::NAN)),
+        (
+            "sqrt".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("sqrt", &params, f64::sqrt, Complex64::sqrt)
+            })),
+        ),
+        (
+            "exp".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("exp", &params, f64::exp, Complex64::exp)
+            })),
+        ),
+        (
+            "log".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("log", &params, 1)?;
+                // The base is optional, and it's natural logarithm by default
+                match (&params[0], params.get(1)) {
+                    (_, None) => unary("log", &params, f64::ln, Complex64::ln),
+                    (Type::Complex(_), Some(base)) | (_, Some(base @ Type::Complex(_))) => {
+                        Ok(Type::Complex(
+                            number::complex(&params[0])?.ln() / number::complex(base)?.ln(),
+                        ))
+                    }
+                    (value, Some(base)) => Ok(Type::Number(
+                        number::float(value)?.ln() / number::float(base)?.ln(),
+                    )),
+                }
+            })),
+        ),
+        (
+            "log2".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("log2", &params, f64::log2, Complex64::log2)
+            })),
+        ),
+        (
+            "log10".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("log10", &params, f64::log10, Complex64::log10)
+            })),
+        ),
+        (
+            "sin".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("sin", &params, f64::sin, Complex64::sin)
+            })),
+        ),
+        (
+            "cos".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("cos", &params, f64::cos, Complex64::cos)
+            })),
+        ),
+        (
+            "tan".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("tan", &params, f64::tan, Complex64::tan)
+            })),
+        ),
+        (
+            "asin".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("asin", &params, f64::asin, Complex64::asin)
+            })),
+        ),
+        (
+            "acos".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("acos", &params, f64::acos, Complex64::acos)
+            })),
+        ),
+        (
+            "atan".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("atan", &params, f64::atan, Complex64::atan)
+            })),
+        ),
+        (
+            "sinh".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("sinh", &params, f64::sinh, Complex64::sinh)
+            })),
+        ),
+        (
+            "cosh".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("cosh", &params, f64::cosh, Complex64::cosh)
+            })),
+        ),
+        (
+            "tanh".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("tanh", &params, f64::tanh, Complex64::tanh)
+            })),
+        ),
+        (
+            "asinh".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("asinh", &params, f64::asinh, Complex64::asinh)
+            })),
+        ),
+        (
+            "acosh".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("acosh", &params, f64::acosh, Complex64::acosh)
+            })),
+        ),
+        (
+            "atanh".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                unary("atanh", &params, f64::atanh, Complex64::atanh)
+            })),
+        ),
+        (
+            "atan2".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("atan2", &params, 2)?;
+                Ok(Type::Number(
+                    number::float(&params[0])?.atan2(number::float(&params[1])?),
+                ))
+            })),
+        ),
+        (
+            "abs".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("abs", &params, 1)?;
+                match &params[0] {
+                    Type::Integer(value) if value.is_negative() => Ok(Type::Integer(-value)),
+                    Type::Rational(value) => Ok(Type::Rational(value.abs())),
+                    Type::Number(value) => Ok(Type::Number(value.abs())),
+                    Type::Complex(value) => Ok(Type::Number(value.norm())),
+                    other => number::number(other),
+                }
+            })),
+        ),
+        (
+            "floor".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                rounding("floor", &params, BigRational::floor, f64::floor)
+            })),
+        ),
+        (
+            "ceil".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                rounding("ceil", &params, BigRational::ceil, f64::ceil)
+            })),
+        ),
+        (
+            "round".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                // The half is rounded away from zero
+                rounding("round", &params, BigRational::round, f64::round)
+            })),
+        ),
+        (
+            "trunc".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                rounding("trunc", &params, BigRational::trunc, f64::trunc)
+            })),
+        ),
+        (
+            "clamp".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("clamp", &params, 3)?;
+                let (value, low, high) = (&params[0], &params[1], &params[2]);
+                if number::compare(low, high)? == Some(Ordering::Greater) {
+                    return Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!(
+                            "the lower bound {} is greater than the upper bound {}",
+                            low.get_symbol(),
+                            high.get_symbol()
+                        ),
+                    ));
+                }
+                if number::compare(value, low)? == Some(Ordering::Less) {
+                    Ok(low.clone())
+                } else if number::compare(value, high)? == Some(Ordering::Greater) {
+                    Ok(high.clone())
+                } else {
+                    Ok(value.clone())
+                }
+            })),
+        ),
+        (
+            "gcd".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                let result = integers("gcd", &params)?
+                    .iter()
+                    .fold(BigInt::zero(), |a, b| a.gcd(b));
+                Ok(Type::Integer(Int::from(result)))
+            })),
+        ),
+        (
+            "lcm".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                let result = integers("lcm", &params)?
+                    .iter()
+                    .fold(BigInt::from(1), |a, b| a.lcm(b));
+                Ok(Type::Integer(Int::from(result)))
+            })),
+        ),
+        (
+            "sign".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("sign", &params, 1)?;
+                match &params[0] {
+                    // Zero and NaN are kept, while `signum` of the float makes zero to one
+                    Type::Number(value) if *value == 0.0 || value.is_nan() => {
+                        Ok(Type::Number(*value))
+                    }
+                    Type::Number(value) => Ok(Type::Number(value.signum())),
+                    Type::Complex(value) if value.is_zero() => Ok(Type::Complex(*value)),
+                    Type::Complex(value) => Ok(Type::Complex(value / value.norm())),
+                    other => {
+                        let order = number::compare(other, &Type::Integer(Int::Small(0)))?;
+                        Ok(Type::Integer(Int::Small(match order {
+                            Some(Ordering::Less) => -1,
+                            Some(Ordering::Greater) => 1,
+                            _ => 0,
+                        })))
+                    }
+                }
+            })),
+        ),
+        (
+            "is-nan".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("is-nan", &params, 1)?;
+                Ok(Type::Bool(match number::number(&params[0])? {
+                    Type::Number(value) => value.is_nan(),
+                    Type::Complex(value) => value.is_nan(),
+                    _ => false,
+                }))
+            })),
+        ),
+        (
+            "is-infinite".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("is-infinite", &params, 1)?;
+                Ok(Type::Bool(match number::number(&params[0])? {
+                    Type::Number(value) => value.is_infinite(),
+                    Type::Complex(value) => value.is_infinite(),
+                    _ => false,
+                }))
+            })),
+        ),
+    ]
+}
@@ -0,0 +1,536 @@
+//! Numeric tower of Pravda, integer < rational < float < complex
+//! The operands are promoted to the higher level before the operation,
+//! and the exact result is kept exact as far as possible
+use crate::error::{ErrorKind, PravdaError};
+use crate::{type_error, Type};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Pow, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// Integer of arbitrary precision
+/// It's machine integer while it fits, so that common arithmetic doesn't allocate
+#[derive(Clone, Debug)]
+pub enum Int {
+    Small(i64),
+    Big(BigInt),
+}
+
+impl Int {
+    /// Parse the integer literal such as `42` or `-7`
+    pub fn parse(text: &str) -> Option<Int> {
+        match text.parse::<i64>() {
+            Ok(value) => Some(Int::Small(value)),
+            Err(_) => text.parse::<BigInt>().ok().map(Int::from),
+        }
+    }
+
+    /// Convert the float to integer if it's whole number
+    pub fn from_f64(value: f64) -> Option<Int> {
+        if !value.is_finite() || value.fract() != 0.0 {
+            return None;
+        }
+        if value.abs() < i64::MAX as f64 {
+            Some(Int::Small(value as i64))
+        } else {
+            num_traits::FromPrimitive::from_f64(value).map(Int::Big)
+        }
+    }
+
+    pub fn to_bigint(&self) -> BigInt {
+        match self {
+            Int::Small(value) => BigInt::from(*value),
+            Int::Big(value) => value.clone(),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Int::Small(value) => *value as f64,
+            Int::Big(value) => value.to_f64().unwrap_or(f64::NAN),
+        }
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        match self {
+            Int::Small(value) => Some(*value),
+            Int::Big(_) => None,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Int::Small(0))
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Int::Small(value) => *value < 0,
+            Int::Big(value) => value.sign() == num_bigint::Sign::Minus,
+        }
+    }
+
+    /// Operate on machine integers, or on big integers if it overflows
+    fn operate(
+        &self,
+        other: &Int,
+        small: fn(i64, i64) -> Option<i64>,
+        big: fn(BigInt, BigInt) -> BigInt,
+    ) -> Int {
+        if let (Int::Small(a), Int::Small(b)) = (self, other) {
+            if let Some(result) = small(*a, *b) {
+                return Int::Small(result);
+            }
+        }
+        Int::from(big(self.to_bigint(), other.to_bigint()))
+    }
+
+    /// Division rounded toward negative infinity, the divisor should not be zero
+    pub fn div_floor(&self, other: &Int) -> Int {
+        self.operate(
+            other,
+            |a, b| (a != i64::MIN || b != -1).then(|| Integer::div_floor(&a, &b)),
+            |a, b| Integer::div_floor(&a, &b),
+        )
+    }
+
+    /// Remainder that has the sign of the divisor, the divisor should not be zero
+    pub fn mod_floor(&self, other: &Int) -> Int {
+        self.operate(
+            other,
+            |a, b| (a != i64::MIN || b != -1).then(|| a.mod_floor(&b)),
+            |a, b| a.mod_floor(&b),
+        )
+    }
+
+    pub fn pow(&self, exponent: u32) -> Int {
+        match self {
+            Int::Small(value) => match value.checked_pow(exponent) {
+                Some(result) => Int::Small(result),
+                None => Int::from(BigInt::from(*value).pow(exponent)),
+            },
+            Int::Big(value) => Int::from(value.pow(exponent)),
+        }
+    }
+}
+
+impl From<i64> for Int {
+    fn from(value: i64) -> Int {
+        Int::Small(value)
+    }
+}
+
+impl From<usize> for Int {
+    fn from(value: usize) -> Int {
+        match i64::try_from(value) {
+            Ok(value) => Int::Small(value),
+            Err(_) => Int::Big(BigInt::from(value)),
+        }
+    }
+}
+
+impl From<BigInt> for Int {
+    fn from(value: BigInt) -> Int {
+        match value.to_i64() {
+            Some(value) => Int::Small(value),
+            None => Int::Big(value),
+        }
+    }
+}
+
+impl Add for &Int {
+    type Output = Int;
+    fn add(self, other: &Int) -> Int {
+        self.operate(other, i64::checked_add, |a, b| a + b)
+    }
+}
+
+impl Sub for &Int {
+    type Output = Int;
+    fn sub(self, other: &Int) -> Int {
+        self.operate(other, i64::checked_sub, |a, b| a - b)
+    }
+}
+
+impl Mul for &Int {
+    type Output = Int;
+    fn mul(self, other: &Int) -> Int {
+        self.operate(other, i64::checked_mul, |a, b| a * b)
+    }
+}
+
+impl Neg for &Int {
+    type Output = Int;
+    fn neg(self) -> Int {
+        match self {
+            Int::Small(value) => match value.checked_neg() {
+                Some(result) => Int::Small(result),
+                None => Int::from(-BigInt::from(*value)),
+            },
+            Int::Big(value) => Int::from(-value),
+        }
+    }
+}
+
+impl PartialEq for Int {
+    fn eq(&self, other: &Int) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Int {}
+
+impl PartialOrd for Int {
+    fn partial_cmp(&self, other: &Int) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Int {
+    fn cmp(&self, other: &Int) -> Ordering {
+        match (self, other) {
+            (Int::Small(a), Int::Small(b)) => a.cmp(b),
+            _ => self.to_bigint().cmp(&other.to_bigint()),
+        }
+    }
+}
+
+impl fmt::Display for Int {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Int::Small(value) => write!(f, "{value}"),
+            Int::Big(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+/// Show the float so that it's distinguished from integer, e.g. `3.0`
+pub fn float_to_string(value: f64) -> String {
+    if value.is_finite() && value.fract() == 0.0 && value.abs() < 1e16 {
+        format!("{value:.1}")
+    } else {
+        value.to_string()
+    }
+}
+
+/// Show the rational as `numerator/denominator`
+pub fn rational_to_string(value: &BigRational) -> String {
+    format!("{}/{}", value.numer(), value.denom())
+}
+
+/// Show the complex number as `real+imaginary i`, e.g. `1+2i`
+pub fn complex_to_string(value: &Complex64) -> String {
+    value.to_string()
+}
+
+/// Operands that are converted to the same level of the numeric tower
+enum Pair {
+    Int(Int, Int),
+    Ratio(BigRational, BigRational),
+    Float(f64, f64),
+    Complex(Complex64, Complex64),
+}
+
+/// Level of the number in the tower, integer < rational < float < complex
+fn level(value: &Type) -> Result<u8, PravdaError> {
+    match value {
+        Type::Integer(_) => Ok(0),
+        Type::Rational(_) => Ok(1),
+        Type::Number(_) => Ok(2),
+        Type::Complex(_) => Ok(3),
+        other => Err(type_error("number", other)),
+    }
+}
+
+fn pair(a: &Type, b: &Type) -> Result<Pair, PravdaError> {
+    Ok(match level(a)?.max(level(b)?) {
+        0 => Pair::Int(integer(a)?, integer(b)?),
+        1 => Pair::Ratio(ratio(a)?, ratio(b)?),
+        2 => Pair::Float(float(a)?, float(b)?),
+        _ => Pair::Complex(complex(a)?, complex(b)?),
+    })
+}
+
+fn division_by_zero() -> PravdaError {
+    PravdaError::new(ErrorKind::Value, "division by zero")
+}
+
+fn not_ordered() -> PravdaError {
+    PravdaError::new(ErrorKind::Value, "complex numbers are not ordered")
+}
+
+/// Make the number from the rational, that's integer if the denominator is 1
+pub fn from_ratio(value: BigRational) -> Type {
+    if value.is_integer() {
+        Type::Integer(Int::from(value.to_integer()))
+    } else {
+        Type::Rational(value)
+    }
+}
+
+pub fn is_number(value: &Type) -> bool {
+    matches!(
+        value,
+        Type::Integer(_) | Type::Rational(_) | Type::Number(_) | Type::Complex(_)
+    )
+}
+
+/// Check that the value is number
+pub fn number(value: &Type) -> Result<Type, PravdaError> {
+    level(value)?;
+    Ok(value.clone())
+}
+
+/// Convert the real number to float
+pub fn float(value: &Type) -> Result<f64, PravdaError> {
+    match value {
+        Type::Integer(value) => Ok(value.to_f64()),
+        Type::Rational(value) => Ok(value.to_f64().unwrap_or(f64::NAN)),
+        Type::Number(value) => Ok(*value),
+        other => Err(type_error("real number", other)),
+    }
+}
+
+/// Convert the number to integer, if it's whole number
+pub fn integer(value: &Type) -> Result<Int, PravdaError> {
+    match value {
+        Type::Integer(value) => Ok(value.clone()),
+        Type::Number(number) => Int::from_f64(*number).ok_or_else(|| {
+            PravdaError::new(
+                ErrorKind::Value,
+                format!("expected integer, but found {}", float_to_string(*number)),
+            )
+        }),
+        other => Err(type_error("integer", other)),
+    }
+}
+
+/// Convert the exact number to rational
+pub fn ratio(value: &Type) -> Result<BigRational, PravdaError> {
+    match value {
+        Type::Integer(value) => Ok(BigRational::from_integer(value.to_bigint())),
+        Type::Rational(value) => Ok(value.clone()),
+        other => Err(type_error("rational", other)),
+    }
+}
+
+/// Convert the number to complex
+pub fn complex(value: &Type) -> Result<Complex64, PravdaError> {
+    match value {
+        Type::Complex(value) => Ok(*value),
+        other => Ok(Complex64::new(float(other)?, 0.0)),
+    }
+}
+
+pub fn add(a: &Type, b: &Type) -> Result<Type, PravdaError> {
+    Ok(match pair(a, b)? {
+        Pair::Int(a, b) => Type::Integer(&a + &b),
+        Pair::Ratio(a, b) => from_ratio(a + b),
+        Pair::Float(a, b) => Type::Number(a + b),
+        Pair::Complex(a, b) => Type::Complex(a + b),
+    })
+}
+
+pub fn sub(a: &Type, b: &Type) -> Result<Type, PravdaError> {
+    Ok(match pair(a, b)? {
+        Pair::Int(a, b) => Type::Integer(&a - &b),
+        Pair::Ratio(a, b) => from_ratio(a - b),
+        Pair::Float(a, b) => Type::Number(a - b),
+        Pair::Complex(a, b) => Type::Complex(a - b),
+    })
+}
+
+pub fn mul(a: &Type, b: &Type) -> Result<Type, PravdaError> {
+    Ok(match pair(a, b)? {
+        Pair::Int(a, b) => Type::Integer(&a * &b),
+        Pair::Ratio(a, b) => from_ratio(a * b),
+        Pair::Float(a, b) => Type::Number(a * b),
+        Pair::Complex(a, b) => Type::Complex(a * b),
+    })
+}
+
+/// Division that's exact if the operands are exact
+pub fn div(a: &Type, b: &Type) -> Result<Type, PravdaError> {
+    Ok(match pair(a, b)? {
+        Pair::Int(_, b) if b.is_zero() => return Err(division_by_zero()),
+        Pair::Int(a, b) => from_ratio(BigRational::new(a.to_bigint(), b.to_bigint())),
+        Pair::Ratio(_, b) if b.is_zero() => return Err(division_by_zero()),
+        Pair::Ratio(a, b) => from_ratio(a / b),
+        Pair::Float(a, b) => Type::Number(a / b),
+        Pair::Complex(a, b) => Type::Complex(a / b),
+    })
+}
+
+/// Integer division rounded toward negative infinity
+pub fn div_floor(a: &Type, b: &Type) -> Result<Type, PravdaError> {
+    Ok(match pair(a, b)? {
+        Pair::Int(_, b) if b.is_zero() => return Err(division_by_zero()),
+        Pair::Int(a, b) => Type::Integer(a.div_floor(&b)),
+        Pair::Ratio(_, b) if b.is_zero() => return Err(division_by_zero()),
+        Pair::Ratio(a, b) => Type::Integer(Int::from((a / b).floor().to_integer())),
+        Pair::Float(a, b) => Type::Number((a / b).floor()),
+        Pair::Complex(..) => return Err(not_ordered()),
+    })
+}
+
+/// Remainder of the floored division, that has the sign of the divisor
+pub fn modulo(a: &Type, b: &Type) -> Result<Type, PravdaError> {
+    Ok(match pair(a, b)? {
+        Pair::Int(_, b) if b.is_zero() => return Err(division_by_zero()),
+        Pair::Int(a, b) => Type::Integer(a.mod_floor(&b)),
+        Pair::Ratio(_, b) if b.is_zero() => return Err(division_by_zero()),
+        Pair::Ratio(a, b) => from_ratio(&a - &b * (&a / &b).floor()),
+        Pair::Float(a, b) => {
+            let remainder = a % b;
+            if remainder != 0.0 && (remainder < 0.0) != (b < 0.0) {
+                Type::Number(remainder + b)
+            } else {
+                Type::Number(remainder)
+            }
+        }
+        Pair::Complex(..) => return Err(not_ordered()),
+    })
+}
+
+/// Maximum bits of the exact result of the power, that's about a million digits
+pub const MAX_BITS: u64 = 1 << 22;
+
+/// Power that's exact if the base is exact and the exponent is integer
+pub fn pow(a: &Type, b: &Type) -> Result<Type, PravdaError> {
+    if let (Type::Integer(_) | Type::Rational(_), Type::Integer(exponent)) = (a, b) {
+        let base = ratio(a)?;
+        if base.is_zero() && exponent.is_negative() {
+            return Err(division_by_zero());
+        }
+        // The power of 0, 1 and -1 doesn't grow, so it's known without the limit
+        if base.is_zero() || base.abs().is_one() {
+            let result = if exponent.is_zero() {
+                BigRational::one()
+            } else if base.is_negative() && exponent.to_bigint().is_even() {
+                -base
+            } else {
+                base
+            };
+            return Ok(from_ratio(result));
+        }
+        // The result grows by the exponent, so it's limited not to run out of memory
+        let bits = base
+            .numer()
+            .bits()
+            .max(base.denom().bits())
+            .saturating_sub(1);
+        let power = exponent.to_i64().and_then(|i| i32::try_from(i).ok());
+        let Some(power) =
+            power.filter(|power| bits.saturating_mul(power.unsigned_abs() as u64) <= MAX_BITS)
+        else {
+            return Err(PravdaError::new(
+                ErrorKind::Value,
+                format!("the result of the power by {exponent} is too large"),
+            ));
+        };
+        return Ok(from_ratio(base.pow(power)));
+    }
+    Ok(match pair(a, b)? {
+        Pair::Int(a, b) => Type::Number(a.to_f64().powf(b.to_f64())),
+        Pair::Ratio(a, b) => Type::Number(float(&from_ratio(a))?.powf(float(&from_ratio(b))?)),
+        Pair::Float(a, b) => Type::Number(a.powf(b)),
+        Pair::Complex(a, b) => Type::Complex(a.powc(b)),
+    })
+}
+
+pub fn negate(a: &Type) -> Result<Type, PravdaError> {
+    Ok(match a {
+        Type::Integer(value) => Type::Integer(-value),
+        Type::Rational(value) => Type::Rational(-value),
+        Type::Complex(value) => Type::Complex(-value),
+        other => Type::Number(-float(other)?),
+    })
+}
+
+/// Compare the real numbers, it's none if either is NaN
+/// The float is compared with the exact number exactly, so that the order is transitive
+pub fn compare(a: &Type, b: &Type) -> Result<Option<Ordering>, PravdaError> {
+    match (a, b) {
+        (Type::Number(a), exact @ (Type::Integer(_) | Type::Rational(_))) => {
+            return compare_exact(*a, exact)
+        }
+        (exact @ (Type::Integer(_) | Type::Rational(_)), Type::Number(b)) => {
+            return Ok(compare_exact(*b, exact)?.map(Ordering::reverse))
+        }
+        _ => {}
+    }
+    Ok(match pair(a, b)? {
+        Pair::Int(a, b) => Some(a.cmp(&b)),
+        Pair::Ratio(a, b) => Some(a.cmp(&b)),
+        Pair::Float(a, b) => a.partial_cmp(&b),
+        Pair::Complex(..) => return Err(not_ordered()),
+    })
+}
+
+/// Compare the float with the exact number, and the infinity is beyond any of them
+fn compare_exact(value: f64, exact: &Type) -> Result<Option<Ordering>, PravdaError> {
+    if value.is_nan() {
+        return Ok(None);
+    }
+    match BigRational::from_float(value) {
+        Some(value) => Ok(Some(value.cmp(&ratio(exact)?))),
+        None if value > 0.0 => Ok(Some(Ordering::Greater)),
+        None => Ok(Some(Ordering::Less)),
+    }
+}
+
+/// Compare the real numbers in the total order, and NaN is greater than any other number
+fn real_cmp(a: &Type, b: &Type) -> Ordering {
+    let is_nan = |value: &Type| matches!(value, Type::Number(value) if value.is_nan());
+    match compare(a, b) {
+        Ok(Some(order)) => order,
+        _ => is_nan(a).cmp(&is_nan(b)),
+    }
+}
+
+/// Compare the numbers in the total order
+/// Complex numbers are compared by the real part and then the imaginary one,
+/// and the real number is the complex one whose imaginary part is zero
+pub fn total_cmp(a: &Type, b: &Type) -> Ordering {
+    if !matches!(a, Type::Complex(_)) && !matches!(b, Type::Complex(_)) {
+        return real_cmp(a, b);
+    }
+    let parts = |value: &Type| match value {
+        Type::Complex(value) => (Type::Number(value.re), Type::Number(value.im)),
+        other => (other.clone(), Type::Integer(Int::Small(0))),
+    };
+    let ((a_re, a_im), (b_re, b_im)) = (parts(a), parts(b));
+    real_cmp(&a_re, &b_re).then_with(|| real_cmp(&a_im, &b_im))
+}
+
+/// Parse the rational literal such as `1/3`
+pub fn parse_rational(text: &str) -> Option<BigRational> {
+    let (numerator, denominator) = text.split_once('/')?;
+    let numerator = Int::parse(numerator)?.to_bigint();
+    let denominator = Int::parse(denominator)?.to_bigint();
+    if denominator.is_zero() || denominator.is_negative() {
+        return None;
+    }
+    Some(BigRational::new(numerator, denominator))
+}
+
+/// Parse the imaginary literal such as `2i` or `0.5i`
+pub fn parse_imaginary(text: &str) -> Option<f64> {
+    text.strip_suffix('i')?.parse().ok()
+}
+
+/// Parse the number literal, integer if it has neither decimal point nor exponent
+pub fn parse_number(text: &str) -> Option<Type> {
+    if let Some(value) = Int::parse(text) {
+        Some(Type::Integer(value))
+    } else if let Some(value) = parse_rational(text) {
+        Some(from_ratio(value))
+    } else if let Some(value) = parse_imaginary(text) {
+        Some(Type::Complex(Complex64::new(0.0, value)))
+    } else {
+        text.parse::<f64>().ok().map(Type::Number)
+    }
+}
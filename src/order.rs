@@ -0,0 +1,100 @@
+//! Structural equality and total order of the values
+//!
+//! The values of different types are ordered as follows:
+//! * null < bool < number < string < symbol < list < set < dict < struct < regex < expr < block < function
+//! * Numbers are compared by the exact value across the numeric tower, so `1` is equal to `1.0`,
+//!   but `2^53 + 1` is greater than the float `2^53.0`.
+//!   Complex numbers are compared by the real and imaginary parts,
+//!   and NaN is equal to itself and greater than any other number including infinity
+//! * Strings and symbols are compared by the code points
+//! * Lists, sets and dicts are compared by the elements from the first one, like dictionary order
+//! * Structs are compared by the fields in the order of their names
+//! * Regular expressions are compared by the patterns
+//! * Lazy expressions and blocks are compared by the code
+//! * Functions are equal only if they are the same function,
+//!   and the order of different functions is not specified
+use crate::number;
+use crate::parser::program_to_string;
+use crate::{Function, Type};
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+/// Rank of the type in the order of the values
+fn rank(value: &Type) -> u8 {
+    match value {
+        Type::Null => 0,
+        Type::Bool(_) => 1,
+        Type::Integer(_) | Type::Rational(_) | Type::Number(_) | Type::Complex(_) => 2,
+        Type::String(_) => 3,
+        Type::Symbol(_) => 4,
+        Type::List(_) => 5,
+        Type::Set(_) => 6,
+        Type::Dict(_) => 7,
+        Type::Struct(_) => 8,
+        Type::Regex(_) => 9,
+        Type::Expr(_) => 10,
+        Type::Block(_) => 11,
+        Type::Function(_) => 12,
+    }
+}
+
+/// Identity of the function, that's kind, name and addresses
+fn identity(function: &Function) -> (u8, &str, Vec<usize>) {
+    match function {
+        Function::BuiltIn(function) => (0, "", vec![*function as usize]),
+        Function::Control(function) => (1, "", vec![*function as usize]),
+        Function::Scoped(function) => (2, "", vec![*function as usize]),
+        Function::UserDefined(name, clauses) => (
+            3,
+            name,
+            clauses
+                .iter()
+                .flat_map(|(_, (body, scope))| [Rc::as_ptr(body) as usize, scope.address()])
+                .collect(),
+        ),
+        Function::Compiled(closure) => (4, &closure.name, vec![Rc::as_ptr(closure) as usize]),
+        Function::Python(code, _) => (5, code, vec![]),
+        Function::Module(program) => (6, "", vec![Rc::as_ptr(program) as usize]),
+    }
+}
+
+impl PartialEq for Type {
+    fn eq(&self, other: &Type) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Type {}
+
+impl PartialOrd for Type {
+    fn partial_cmp(&self, other: &Type) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Type {
+    fn cmp(&self, other: &Type) -> Ordering {
+        match (self, other) {
+            (Type::Bool(a), Type::Bool(b)) => a.cmp(b),
+            (Type::String(a), Type::String(b)) | (Type::Symbol(a), Type::Symbol(b)) => a.cmp(b),
+            (Type::List(a), Type::List(b)) => a.cmp(b),
+            (Type::Set(a), Type::Set(b)) => a.cmp(b),
+            (Type::Dict(a), Type::Dict(b)) => a.cmp(b),
+            (Type::Struct(a), Type::Struct(b)) => {
+                let mut a: Vec<_> = a.iter().collect();
+                let mut b: Vec<_> = b.iter().collect();
+                a.sort_by(|x, y| x.0.cmp(y.0));
+                b.sort_by(|x, y| x.0.cmp(y.0));
+                a.cmp(&b)
+            }
+            (Type::Regex(a), Type::Regex(b)) => a.as_str().cmp(b.as_str()),
+            (Type::Expr(a), Type::Expr(b)) => a.to_string().cmp(&b.to_string()),
+            (Type::Block(a), Type::Block(b)) => program_to_string(a).cmp(&program_to_string(b)),
+            (Type::Function(a), Type::Function(b)) => identity(a).cmp(&identity(b)),
+            _ if number::is_number(self) && number::is_number(other) => {
+                number::total_cmp(self, other)
+            }
+            _ => rank(self).cmp(&rank(other)),
+        }
+    }
+}
@@ -0,0 +1,545 @@
+//! Parser that builds the abstract syntax tree from tokens
+use crate::diagnostic::Diagnostic;
+use crate::format::Spec;
+use crate::lexer::{escape, tokenize, Fragment, Source, Span, Token, TokenKind};
+use crate::number::{float_to_string, parse_imaginary, parse_rational, rational_to_string, Int};
+use num_rational::BigRational;
+use std::fmt;
+use std::rc::Rc;
+
+/// Node of the abstract syntax tree
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub span: Span,
+}
+
+/// Kind of the node
+#[derive(Clone, Debug)]
+pub enum NodeKind {
+    /// Integer literal, e.g. `42`
+    Integer(Int),
+    /// Rational literal, e.g. `1/3`
+    Rational(BigRational),
+    /// Imaginary literal, e.g. `2i`
+    Imaginary(f64),
+    /// Float literal, e.g. `3.14`
+    Number(f64),
+    /// String literal, e.g. `"hello"`
+    String(String),
+    /// Bool literal, `true` or `false`
+    Bool(bool),
+    /// Null literal, `null`
+    Null,
+    /// Symbol such as a variable name, e.g. `x`
+    Symbol(String),
+    /// Expression that's sequence of terms, e.g. `(+ 1 2)`
+    Expr(Vec<Node>),
+    /// Code block that has several lines, e.g. `{ x = 5; + x 1 }`
+    Block(Rc<Vec<Stmt>>),
+    /// List literal, e.g. `[1 2 "abc"]`
+    List(Vec<Node>),
+    /// Lambda expression that has arguments and body, e.g. `\(n -> * n 2)`
+    Lambda(Vec<Node>, Rc<Node>),
+    /// Struct literal that has fields, e.g. `struct{ a = 1; b = 2 }`
+    Struct(Vec<(String, Node)>),
+    /// Dictionary literal that has entries, e.g. `dict{ "a" -> 1; 2 -> "b" }`
+    Dict(Vec<(Node, Node)>),
+    /// Set literal that has elements, e.g. `set{ 1 2 3 }`
+    Set(Vec<Node>),
+    /// Lazy evaluated term, e.g. `@(+ 1 2)`
+    Lazy(Rc<Node>),
+    /// Term to expand as mutable length argument, e.g. `~args`
+    Spread(Box<Node>),
+    /// Interpolated string, e.g. `f"total: {(+ a b)}"`
+    Format(Vec<Part>),
+}
+
+/// Part of the interpolated string
+#[derive(Clone, Debug)]
+pub enum Part {
+    Text(String),
+    /// Expression to embed and its format spec
+    Value(Node, Option<Spec>),
+}
+
+/// Line of the program
+#[derive(Clone, Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+/// Kind of the line
+#[derive(Clone, Debug)]
+pub enum StmtKind {
+    /// Expression to evaluate, e.g. `print "hello"`
+    Expr(Node),
+    /// Definition of variable, e.g. `x = 5`
+    Define(String, Node),
+    /// Definition of function clause, e.g. `fact n = * n (fact (- n 1))`
+    Function(String, Vec<Node>, Rc<Node>),
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            NodeKind::Integer(value) => write!(f, "{value}"),
+            NodeKind::Rational(value) => write!(f, "{}", rational_to_string(value)),
+            NodeKind::Imaginary(value) => write!(f, "{value}i"),
+            NodeKind::Number(value) => write!(f, "{}", float_to_string(*value)),
+            NodeKind::String(value) => write!(f, "\"{}\"", escape(value)),
+            NodeKind::Bool(value) => write!(f, "{value}"),
+            NodeKind::Null => write!(f, "null"),
+            NodeKind::Symbol(name) => write!(f, "{name}"),
+            NodeKind::Expr(terms) => write!(f, "({})", join(terms)),
+            NodeKind::Block(program) => write!(f, "{{ {} }}", program_to_string(program)),
+            NodeKind::List(items) => write!(f, "[{}]", join(items)),
+            NodeKind::Lambda(args, body) => {
+                write!(f, "\\({} -> {})", join(args), Body(body))
+            }
+            NodeKind::Struct(fields) => write!(
+                f,
+                "struct{{ {} }}",
+                fields
+                    .iter()
+                    .map(|(k, v)| format!("{k} = {}", Body(v)))
+                    .collect::<Vec<String>>()
+                    .join("; ")
+            ),
+            NodeKind::Dict(entries) if entries.is_empty() => write!(f, "dict{{}}"),
+            NodeKind::Dict(entries) => write!(
+                f,
+                "dict{{ {} }}",
+                entries
+                    .iter()
+                    .map(|(k, v)| format!("{} -> {}", Body(k), Body(v)))
+                    .collect::<Vec<String>>()
+                    .join("; ")
+            ),
+            NodeKind::Set(items) if items.is_empty() => write!(f, "set{{}}"),
+            NodeKind::Set(items) => write!(f, "set{{ {} }}", join(items)),
+            NodeKind::Lazy(term) => write!(f, "@{term}"),
+            NodeKind::Spread(term) => write!(f, "~{term}"),
+            NodeKind::Format(parts) => {
+                write!(f, "f\"")?;
+                for part in parts {
+                    match part {
+                        Part::Text(text) => {
+                            write!(f, "{}", escape(text).replace('{', "{{").replace('}', "}}"))?
+                        }
+                        Part::Value(expr, Some(spec)) => write!(f, "{{{}:{spec}}}", Body(expr))?,
+                        Part::Value(expr, None) => write!(f, "{{{}}}", Body(expr))?,
+                    }
+                }
+                write!(f, "\"")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            StmtKind::Expr(expr) => write!(f, "{}", Body(expr)),
+            StmtKind::Define(name, value) => write!(f, "{name} = {}", Body(value)),
+            StmtKind::Function(name, args, body) => {
+                write!(f, "{name} {} = {}", join(args), Body(body))
+            }
+        }
+    }
+}
+
+/// Expression written without surrounding parentheses
+struct Body<'a>(&'a Node);
+
+impl fmt::Display for Body<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0.kind {
+            NodeKind::Expr(terms) => write!(f, "{}", join(terms)),
+            _ => write!(f, "{}", self.0),
+        }
+    }
+}
+
+fn join(nodes: &[Node]) -> String {
+    nodes
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Show lines of the program separated by `;`
+pub fn program_to_string(program: &[Stmt]) -> String {
+    program
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+/// Parse the source code as the program
+/// # Arguments
+/// * `name` - File path or name of the input to show in diagnostics
+/// * `code` - The source code string to parse
+/// # Return values
+/// The statements of the program, e.g. two of them for `x = 5; + x 1`
+pub fn parse(name: &str, code: &str) -> Result<Vec<Stmt>, Diagnostic> {
+    let source = Rc::new(Source {
+        name: name.to_string(),
+        code: code.to_string(),
+    });
+    let mut parser = Parser {
+        tokens: tokenize(source.clone())?
+            .into_iter()
+            .filter(|i| !matches!(i.kind, TokenKind::Comment(_)))
+            .collect(),
+        index: 0,
+        end: Span {
+            start: code.len(),
+            end: code.len(),
+            line: code.split('\n').count(),
+            column: code.rsplit('\n').next().unwrap_or_default().chars().count() + 1,
+            source,
+        },
+    };
+    let program = parser.parse_program()?;
+    if let Some(token) = parser.peek() {
+        return Err(unexpected(token));
+    }
+    Ok(program)
+}
+
+/// Show the token in the error message
+fn describe(kind: &TokenKind) -> String {
+    match kind {
+        TokenKind::LeftParen => "`(`".to_string(),
+        TokenKind::RightParen => "`)`".to_string(),
+        TokenKind::LeftBracket => "`[`".to_string(),
+        TokenKind::RightBracket => "`]`".to_string(),
+        TokenKind::LeftBrace => "`{`".to_string(),
+        TokenKind::RightBrace => "`}`".to_string(),
+        TokenKind::Lambda => "`lambda(`".to_string(),
+        TokenKind::Struct => "`struct{`".to_string(),
+        TokenKind::Dict => "`dict{`".to_string(),
+        TokenKind::Set => "`set{`".to_string(),
+        TokenKind::Equal => "`=`".to_string(),
+        TokenKind::Semicolon => "`;`".to_string(),
+        TokenKind::Arrow => "`->`".to_string(),
+        TokenKind::Lazy => "`@`".to_string(),
+        TokenKind::Spread => "`~`".to_string(),
+        TokenKind::String(value) => format!("\"{value}\""),
+        TokenKind::Word(word) => format!("`{word}`"),
+        TokenKind::Format(_) => "interpolated string".to_string(),
+        TokenKind::Comment(_) => "comment".to_string(),
+    }
+}
+
+fn unexpected(token: &Token) -> Diagnostic {
+    Diagnostic::new(
+        format!("unexpected token {}", describe(&token.kind)),
+        token.span.clone(),
+    )
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+    /// Empty span at the end of the code
+    end: Span,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn peek_kind(&self) -> Option<&TokenKind> {
+        self.peek().map(|token| &token.kind)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).cloned();
+        self.index += 1;
+        token
+    }
+
+    /// Empty span in front of the next token
+    fn here(&self) -> Span {
+        match self.peek() {
+            Some(token) => Span {
+                end: token.span.start,
+                ..token.span.clone()
+            },
+            None => self.end.clone(),
+        }
+    }
+
+    /// Span of the token consumed at last
+    fn last(&self) -> Span {
+        match self.index.checked_sub(1).and_then(|i| self.tokens.get(i)) {
+            Some(token) => token.span.clone(),
+            None => self.end.clone(),
+        }
+    }
+
+    /// Consume the token if it's expected one
+    fn eat(&mut self, expected: &TokenKind) -> bool {
+        if self.peek_kind() == Some(expected) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consume the expected token that closes the opening one
+    fn expect(&mut self, expected: TokenKind, opening: &Span) -> Result<(), Diagnostic> {
+        match self.next() {
+            Some(token) if token.kind == expected => Ok(()),
+            Some(token) => Err(Diagnostic::new(
+                format!(
+                    "expected {} but found {}",
+                    describe(&expected),
+                    describe(&token.kind)
+                ),
+                token.span,
+            )),
+            None => Err(Diagnostic::new(
+                format!("expected {} but it's ended", describe(&expected)),
+                opening.clone(),
+            )),
+        }
+    }
+
+    /// Parse lines separated by `;` until `}` or end of the code
+    fn parse_program(&mut self) -> Result<Vec<Stmt>, Diagnostic> {
+        let mut program = Vec::new();
+        loop {
+            while self.eat(&TokenKind::Semicolon) {}
+            match self.peek_kind() {
+                None | Some(TokenKind::RightBrace) => return Ok(program),
+                _ => program.push(self.parse_stmt()?),
+            }
+            match self.peek() {
+                None => {}
+                Some(token)
+                    if matches!(token.kind, TokenKind::RightBrace | TokenKind::Semicolon) => {}
+                Some(token) => return Err(unexpected(token)),
+            }
+        }
+    }
+
+    /// Parse a line that's expression or definition
+    fn parse_stmt(&mut self) -> Result<Stmt, Diagnostic> {
+        let define = self.parse_expr()?;
+        if !self.eat(&TokenKind::Equal) {
+            return Ok(Stmt {
+                span: define.span.clone(),
+                kind: StmtKind::Expr(define),
+            });
+        }
+
+        let value = self.parse_expr()?;
+        let span = define.span.to(&value.span);
+        let NodeKind::Expr(mut terms) = define.kind else {
+            unreachable!()
+        };
+        match terms.first() {
+            Some(Node {
+                kind: NodeKind::Symbol(name),
+                ..
+            }) => {
+                let name = name.clone();
+                let kind = if terms.len() == 1 {
+                    // Define variable
+                    StmtKind::Define(name, value)
+                } else {
+                    // Define function
+                    let args = terms.split_off(1);
+                    StmtKind::Function(name, args, Rc::new(value))
+                };
+                Ok(Stmt { kind, span })
+            }
+            Some(other) => Err(Diagnostic::new(
+                format!("can't define {other} as variable or function"),
+                other.span.clone(),
+            )),
+            None => Err(Diagnostic::new("the name to define is missing", span)),
+        }
+    }
+
+    /// Parse an expression without parentheses, until the token that ends it
+    fn parse_expr(&mut self) -> Result<Node, Diagnostic> {
+        let start = self.here();
+        let terms = self.parse_terms()?;
+        let span = match (terms.first(), terms.last()) {
+            (Some(first), Some(last)) => first.span.to(&last.span),
+            _ => start,
+        };
+        Ok(Node {
+            kind: NodeKind::Expr(terms),
+            span,
+        })
+    }
+
+    /// Parse terms until the token that ends expression
+    fn parse_terms(&mut self) -> Result<Vec<Node>, Diagnostic> {
+        let mut terms = Vec::new();
+        while let Some(kind) = self.peek_kind() {
+            match kind {
+                TokenKind::RightParen
+                | TokenKind::RightBracket
+                | TokenKind::RightBrace
+                | TokenKind::Semicolon
+                | TokenKind::Equal
+                | TokenKind::Arrow => break,
+                _ => terms.push(self.parse_term()?),
+            }
+        }
+        Ok(terms)
+    }
+
+    fn parse_term(&mut self) -> Result<Node, Diagnostic> {
+        let Some(Token { kind, span: start }) = self.next() else {
+            return Err(Diagnostic::new(
+                "the code is ended unexpectedly",
+                self.end.clone(),
+            ));
+        };
+        let kind = match kind {
+            TokenKind::Word(word) => parse_word(word),
+            TokenKind::String(value) => NodeKind::String(value),
+            TokenKind::Format(fragments) => NodeKind::Format(
+                fragments
+                    .into_iter()
+                    .map(parse_fragment)
+                    .collect::<Result<Vec<Part>, Diagnostic>>()?,
+            ),
+            TokenKind::LeftParen => {
+                let terms = self.parse_terms()?;
+                self.expect(TokenKind::RightParen, &start)?;
+                NodeKind::Expr(terms)
+            }
+            TokenKind::LeftBrace => {
+                let program = self.parse_program()?;
+                self.expect(TokenKind::RightBrace, &start)?;
+                NodeKind::Block(Rc::new(program))
+            }
+            TokenKind::LeftBracket => {
+                let items = self.parse_terms()?;
+                self.expect(TokenKind::RightBracket, &start)?;
+                NodeKind::List(items)
+            }
+            TokenKind::Lambda => {
+                let args = self.parse_terms()?;
+                self.expect(TokenKind::Arrow, &start)?;
+                let body = self.parse_expr()?;
+                self.expect(TokenKind::RightParen, &start)?;
+                NodeKind::Lambda(args, Rc::new(body))
+            }
+            TokenKind::Struct => {
+                let mut fields = Vec::new();
+                for line in self.parse_program()? {
+                    match line.kind {
+                        StmtKind::Define(name, value) => fields.push((name, value)),
+                        _ => {
+                            return Err(Diagnostic::new(
+                                format!("struct field should be `name = value`, not `{line}`"),
+                                line.span,
+                            ))
+                        }
+                    }
+                }
+                self.expect(TokenKind::RightBrace, &start)?;
+                NodeKind::Struct(fields)
+            }
+            TokenKind::Dict => {
+                let mut entries = Vec::new();
+                loop {
+                    while self.eat(&TokenKind::Semicolon) {}
+                    if matches!(self.peek_kind(), Some(TokenKind::RightBrace) | None) {
+                        break;
+                    }
+                    let key = self.parse_expr()?;
+                    self.expect(TokenKind::Arrow, &start)?;
+                    let value = self.parse_expr()?;
+                    entries.push((key, value));
+                    if !self.eat(&TokenKind::Semicolon) {
+                        break;
+                    }
+                }
+                self.expect(TokenKind::RightBrace, &start)?;
+                NodeKind::Dict(entries)
+            }
+            TokenKind::Set => {
+                let items = self.parse_terms()?;
+                self.expect(TokenKind::RightBrace, &start)?;
+                NodeKind::Set(items)
+            }
+            TokenKind::Lazy => NodeKind::Lazy(Rc::new(self.parse_term()?)),
+            TokenKind::Spread => NodeKind::Spread(Box::new(self.parse_term()?)),
+            kind => {
+                return Err(Diagnostic::new(
+                    format!("unexpected token {}", describe(&kind)),
+                    start,
+                ))
+            }
+        };
+        Ok(Node {
+            kind,
+            span: start.to(&self.last()),
+        })
+    }
+}
+
+/// Parse the embedded code of the interpolated string
+fn parse_fragment(fragment: Fragment) -> Result<Part, Diagnostic> {
+    let (tokens, spec, span) = match fragment {
+        Fragment::Text(text) => return Ok(Part::Text(text)),
+        Fragment::Code { tokens, spec, span } => (tokens, spec, span),
+    };
+    let mut parser = Parser {
+        tokens: tokens
+            .into_iter()
+            .filter(|i| !matches!(i.kind, TokenKind::Comment(_)))
+            .collect(),
+        index: 0,
+        end: span.clone(),
+    };
+    if parser.peek().is_none() {
+        return Err(Diagnostic::new("the interpolation is empty", span));
+    }
+    let expr = parser.parse_expr()?;
+    if let Some(token) = parser.peek() {
+        return Err(unexpected(token));
+    }
+    let spec = match spec {
+        Some(spec) => Some(Spec::parse(&spec).map_err(|message| Diagnostic::new(message, span))?),
+        None => None,
+    };
+    Ok(Part::Value(expr, spec))
+}
+
+/// Classify the word into literal or symbol
+fn parse_word(word: String) -> NodeKind {
+    let is_number = word
+        .trim_start_matches(['+', '-'])
+        .starts_with(|c: char| c.is_ascii_digit() || c == '.');
+    if let (true, Some(value)) = (is_number, Int::parse(&word)) {
+        NodeKind::Integer(value)
+    } else if let (true, Some(value)) = (is_number, parse_rational(&word)) {
+        NodeKind::Rational(value)
+    } else if let (true, Some(value)) = (is_number, parse_imaginary(&word)) {
+        NodeKind::Imaginary(value)
+    } else if let (true, Ok(value)) = (is_number, word.parse::<f64>()) {
+        NodeKind::Number(value)
+    } else if let Ok(value) = word.parse::<bool>() {
+        NodeKind::Bool(value)
+    } else if word == "null" {
+        NodeKind::Null
+    } else {
+        NodeKind::Symbol(word)
+    }
+}
@@ -0,0 +1,173 @@
+//! Built-in functions of the random numbers
+//!
+//! The generator is seeded by the entropy of OS, or the seed given by `random-seed` or `--seed`.
+//! It's ChaCha8 that's portable, so the same seed makes the same sequence on any platform
+use crate::error::{ErrorKind, PravdaError};
+use crate::number::{self, Int};
+use crate::{check_arity, type_error, Function, Type};
+use im_rc::Vector;
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::cell::RefCell;
+
+thread_local! {
+    /// Generator of the random numbers that's shared by the program
+    static RNG: RefCell<ChaCha8Rng> = RefCell::new(ChaCha8Rng::from_entropy());
+}
+
+/// Reset the generator by the seed, so that the sequence is reproducible
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = ChaCha8Rng::seed_from_u64(seed));
+}
+
+/// Run the function with the generator
+fn with_rng<T>(f: impl FnOnce(&mut ChaCha8Rng) -> T) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+/// Get the list from the value, or error if it's other type
+fn list(value: &Type) -> Result<&Vector<Type>, PravdaError> {
+    match value {
+        Type::List(list) => Ok(list),
+        other => Err(type_error("list", other)),
+    }
+}
+
+/// Get the integer that fits in 64 bits
+fn int(value: &Type) -> Result<i64, PravdaError> {
+    number::integer(value)?.to_i64().ok_or_else(|| {
+        PravdaError::new(
+            ErrorKind::Value,
+            format!("the integer {} is too large", value.get_symbol()),
+        )
+    })
+}
+
+/// Error of choosing from the empty list
+fn empty_list(name: &str) -> PravdaError {
+    PravdaError::new(
+        ErrorKind::Value,
+        format!("`{name}` can't choose from empty list"),
+    )
+}
+
+pub fn builtins() -> Vec<(String, Type)> {
+    vec![
+        (
+            "random".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                // It's in [0, 1) by default, or in [low, high) if the range is given
+                let (low, high) = if params.is_empty() {
+                    (0.0, 1.0)
+                } else {
+                    check_arity("random", &params, 2)?;
+                    (number::float(&params[0])?, number::float(&params[1])?)
+                };
+                if low >= high || !(high - low).is_finite() {
+                    return Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!("the range {low}..{high} is empty or infinite"),
+                    ));
+                }
+                Ok(Type::Number(with_rng(|rng| rng.gen_range(low..high))))
+            })),
+        ),
+        (
+            "random-int".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("random-int", &params, 2)?;
+                // Both of the bounds are included
+                let (low, high) = (int(&params[0])?, int(&params[1])?);
+                if low > high {
+                    return Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!("the range {low}..={high} is empty"),
+                    ));
+                }
+                Ok(Type::Integer(Int::Small(with_rng(|rng| {
+                    rng.gen_range(low..=high)
+                }))))
+            })),
+        ),
+        (
+            "random-seed".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("random-seed", &params, 1)?;
+                seed(int(&params[0])? as u64);
+                Ok(Type::Null)
+            })),
+        ),
+        (
+            "shuffle".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("shuffle", &params, 1)?;
+                let mut items: Vec<Type> = list(&params[0])?.iter().cloned().collect();
+                with_rng(|rng| items.shuffle(rng));
+                Ok(Type::List(items.into()))
+            })),
+        ),
+        (
+            "choice".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("choice", &params, 1)?;
+                let items = list(&params[0])?;
+                if items.is_empty() {
+                    return Err(empty_list("choice"));
+                }
+                let index = with_rng(|rng| rng.gen_range(0..items.len()));
+                Ok(items[index].clone())
+            })),
+        ),
+        (
+            "sample".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("sample", &params, 2)?;
+                // The elements are chosen without replacement
+                let items = list(&params[0])?;
+                let count = int(&params[1])?;
+                let Some(count) = usize::try_from(count).ok().filter(|&i| i <= items.len()) else {
+                    return Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!(
+                            "can't sample {count} elements from the list of length {}",
+                            items.len()
+                        ),
+                    ));
+                };
+                let indices = with_rng(|rng| rand::seq::index::sample(rng, items.len(), count));
+                Ok(Type::List(
+                    indices.into_iter().map(|i| items[i].clone()).collect(),
+                ))
+            })),
+        ),
+        (
+            "weighted-choice".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("weighted-choice", &params, 2)?;
+                let items = list(&params[0])?;
+                let weights = list(&params[1])?
+                    .iter()
+                    .map(number::float)
+                    .collect::<Result<Vec<f64>, _>>()?;
+                if items.len() != weights.len() {
+                    return Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!(
+                            "the list of length {} has {} weights",
+                            items.len(),
+                            weights.len()
+                        ),
+                    ));
+                }
+                if items.is_empty() {
+                    return Err(empty_list("weighted-choice"));
+                }
+                let distribution = WeightedIndex::new(&weights).map_err(|err| {
+                    PravdaError::new(ErrorKind::Value, format!("invalid weights: {err}"))
+                })?;
+                Ok(items[with_rng(|rng| distribution.sample(rng))].clone())
+            })),
+        ),
+    ]
+}
@@ -0,0 +1,155 @@
+//! Built-in functions of the regular expressions
+//!
+//! The pattern can be given as the compiled regex or the string,
+//! and the string is compiled every time it's called
+use crate::error::{ErrorKind, PravdaError};
+use crate::lexer::escape;
+use crate::number;
+use crate::string::string;
+use crate::{call_function, check_arity, type_error, Function, Type};
+use ::regex::{Regex, RegexBuilder};
+use im_rc::HashMap as ImHashMap;
+
+/// Compile the pattern with the flags, that are some of `imsxU`
+fn compile(pattern: &str, flags: &str) -> Result<Regex, PravdaError> {
+    let mut builder = RegexBuilder::new(pattern);
+    for flag in flags.chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            'U' => builder.swap_greed(true),
+            other => {
+                return Err(PravdaError::new(
+                    ErrorKind::Value,
+                    format!("unknown regex flag `{other}`"),
+                ))
+            }
+        };
+    }
+    builder.build().map_err(|err| {
+        // The syntax error shows the pattern over several lines, so only the reason is kept
+        let detail = err.to_string();
+        let reason = detail.lines().last().unwrap_or_default();
+        PravdaError::new(
+            ErrorKind::Value,
+            format!(
+                "invalid regex \"{}\": {}",
+                escape(pattern),
+                reason.trim_start_matches("error: ")
+            ),
+        )
+    })
+}
+
+/// Get the regex from the value, or compile it if it's string
+fn regex(value: &Type) -> Result<Regex, PravdaError> {
+    match value {
+        Type::Regex(regex) => Ok(regex.clone()),
+        Type::String(pattern) => compile(pattern, ""),
+        other => Err(type_error("regex or string", other)),
+    }
+}
+
+pub fn builtins() -> Vec<(String, Type)> {
+    vec![
+        (
+            "regex".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("regex", &params, 1)?;
+                let flags = match params.get(1) {
+                    Some(flags) => string(flags)?,
+                    None => "",
+                };
+                Ok(Type::Regex(compile(string(&params[0])?, flags)?))
+            })),
+        ),
+        (
+            "re-match".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("re-match", &params, 2)?;
+                Ok(Type::Bool(regex(&params[0])?.is_match(string(&params[1])?)))
+            })),
+        ),
+        (
+            "re-find-all".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("re-find-all", &params, 2)?;
+                Ok(Type::List(
+                    regex(&params[0])?
+                        .find_iter(string(&params[1])?)
+                        .map(|found| Type::String(found.as_str().to_string()))
+                        .collect(),
+                ))
+            })),
+        ),
+        (
+            "re-captures".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("re-captures", &params, 2)?;
+                let regex = regex(&params[0])?;
+                let Some(captures) = regex.captures(string(&params[1])?) else {
+                    return Ok(Type::Null);
+                };
+                // The named groups become the fields, and unmatched one is null
+                let mut fields = ImHashMap::new();
+                for name in regex.capture_names().flatten() {
+                    let value = match captures.name(name) {
+                        Some(group) => Type::String(group.as_str().to_string()),
+                        None => Type::Null,
+                    };
+                    fields.insert(name.to_string(), value);
+                }
+                Ok(Type::Struct(fields))
+            })),
+        ),
+        (
+            "re-replace".to_string(),
+            Type::Function(Function::BuiltIn(|params, memory| {
+                check_arity("re-replace", &params, 3)?;
+                let regex = regex(&params[0])?;
+                let text = string(&params[1])?;
+                let limit = match params.get(3) {
+                    Some(count) => Some(number::integer(count)?.to_i64().unwrap_or(i64::MAX)),
+                    None => None,
+                };
+
+                // The replacement is the template like `$1`, or the function of the matched text
+                let mut result = String::new();
+                let mut last = 0;
+                for (index, captures) in regex.captures_iter(text).enumerate() {
+                    if limit.is_some_and(|limit| index as i64 >= limit) {
+                        break;
+                    }
+                    let found = captures.get(0).unwrap();
+                    result.push_str(&text[last..found.start()]);
+                    match &params[2] {
+                        Type::Function(function) => {
+                            let matched = vec![Type::String(found.as_str().to_string())];
+                            result.push_str(
+                                &call_function(function.clone(), matched, memory)?.get_string(),
+                            );
+                        }
+                        other => captures.expand(string(other)?, &mut result),
+                    }
+                    last = found.end();
+                }
+                result.push_str(&text[last..]);
+                Ok(Type::String(result))
+            })),
+        ),
+        (
+            "re-split".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("re-split", &params, 2)?;
+                Ok(Type::List(
+                    regex(&params[0])?
+                        .split(string(&params[1])?)
+                        .map(|i| Type::String(i.to_string()))
+                        .collect(),
+                ))
+            })),
+        ),
+    ]
+}
@@ -0,0 +1,43 @@
+//! Set of Pravda, that's ordered by the elements in the same way as the keys of dictionary
+use crate::dict::Key;
+use crate::error::PravdaError;
+use crate::{type_error, Type};
+use im_rc::OrdSet;
+
+/// Elements of the set
+pub type Set = OrdSet<Key>;
+
+/// Get the set from the value, or error if it's other type
+pub fn set(value: &Type) -> Result<&Set, PravdaError> {
+    match value {
+        Type::Set(set) => Ok(set),
+        other => Err(type_error("set", other)),
+    }
+}
+
+/// Make the set from the elements, or error if there's unhashable one
+/// If the elements are duplicated, the first one is kept
+pub fn to_set(items: impl IntoIterator<Item = Type>) -> Result<Set, PravdaError> {
+    let mut set = Set::new();
+    for item in items {
+        let key = Key::new(&item)?;
+        if !set.contains(&key) {
+            set.insert(key);
+        }
+    }
+    Ok(set)
+}
+
+/// Show the set as the literal, e.g. `set{ 1 2 3 }`
+pub fn set_to_string(set: &Set) -> String {
+    if set.is_empty() {
+        return "set{}".to_string();
+    }
+    format!(
+        "set{{ {} }}",
+        set.iter()
+            .map(|item| item.to_type().get_symbol())
+            .collect::<Vec<String>>()
+            .join(" ")
+    )
+}
@@ -0,0 +1,301 @@
+//! Built-in functions to process the strings
+//!
+//! The strings are indexed and counted by the characters (Unicode scalar values),
+//! in the same way as they are split into the list of characters
+use crate::error::{ErrorKind, PravdaError};
+use crate::number::{self, Int};
+use crate::{check_arity, type_error, Function, Type};
+use im_rc::Vector;
+
+/// Maximum length of the string that's made by repeating or padding,
+/// so that it doesn't run out of memory
+pub const MAX_LENGTH: usize = 1 << 24;
+
+/// Get the string from the value, or error if it's other type
+pub fn string(value: &Type) -> Result<&str, PravdaError> {
+    match value {
+        Type::String(text) => Ok(text),
+        other => Err(type_error("string", other)),
+    }
+}
+
+/// Find the first occurrence of the pattern, and get its index by the characters
+pub fn index_of(text: &str, pattern: &str) -> Option<usize> {
+    text.find(pattern).map(|byte| text[..byte].chars().count())
+}
+
+/// Get the index or width, that should be non-negative integer
+fn size(value: &Type) -> Result<usize, PravdaError> {
+    number::integer(value)?
+        .to_i64()
+        .and_then(|size| usize::try_from(size).ok())
+        .ok_or_else(|| {
+            PravdaError::new(
+                ErrorKind::Value,
+                format!(
+                    "the index should be non-negative integer, not {}",
+                    value.get_symbol()
+                ),
+            )
+        })
+}
+
+/// Error if the length of the result is over the limit
+fn check_length(name: &str, length: Option<usize>) -> Result<(), PravdaError> {
+    match length {
+        Some(length) if length <= MAX_LENGTH => Ok(()),
+        _ => Err(PravdaError::new(
+            ErrorKind::Value,
+            format!("the result of `{name}` should be at most {MAX_LENGTH} long"),
+        )),
+    }
+}
+
+/// Get the fill character of the padding, that's space by default
+fn fill(value: Option<&Type>) -> Result<char, PravdaError> {
+    let Some(value) = value else {
+        return Ok(' ');
+    };
+    let mut chars = string(value)?.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(PravdaError::new(
+            ErrorKind::Value,
+            format!(
+                "the fill should be single character, not {}",
+                value.get_symbol()
+            ),
+        )),
+    }
+}
+
+/// Make the list of the strings
+fn strings<'a>(items: impl Iterator<Item = &'a str>) -> Type {
+    Type::List(items.map(|i| Type::String(i.to_string())).collect())
+}
+
+pub fn builtins() -> Vec<(String, Type)> {
+    vec![
+        (
+            "substring".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("substring", &params, 2)?;
+                let text = string(&params[0])?;
+                let length = text.chars().count();
+                let start = size(&params[1])?;
+                let end = match params.get(2) {
+                    Some(end) => size(end)?,
+                    None => length,
+                };
+                if start > end || end > length {
+                    return Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!("the range {start}..{end} is out of range for the string of length {length}"),
+                    ));
+                }
+                Ok(Type::String(
+                    text.chars().skip(start).take(end - start).collect(),
+                ))
+            })),
+        ),
+        (
+            "starts-with".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("starts-with", &params, 2)?;
+                Ok(Type::Bool(
+                    string(&params[0])?.starts_with(string(&params[1])?),
+                ))
+            })),
+        ),
+        (
+            "ends-with".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("ends-with", &params, 2)?;
+                Ok(Type::Bool(
+                    string(&params[0])?.ends_with(string(&params[1])?),
+                ))
+            })),
+        ),
+        (
+            "replace".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("replace", &params, 3)?;
+                let text = string(&params[0])?;
+                let from = string(&params[1])?;
+                let to = string(&params[2])?;
+                if from.is_empty() {
+                    return Err(PravdaError::new(
+                        ErrorKind::Value,
+                        "the string to be replaced shouldn't be empty",
+                    ));
+                }
+                // The count of the replacement is optional, and all are replaced by default
+                Ok(Type::String(match params.get(3) {
+                    Some(count) => text.replacen(from, to, size(count)?),
+                    None => text.replace(from, to),
+                }))
+            })),
+        ),
+        (
+            "trim".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("trim", &params, 1)?;
+                Ok(Type::String(string(&params[0])?.trim().to_string()))
+            })),
+        ),
+        (
+            "trim-start".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("trim-start", &params, 1)?;
+                Ok(Type::String(string(&params[0])?.trim_start().to_string()))
+            })),
+        ),
+        (
+            "trim-end".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("trim-end", &params, 1)?;
+                Ok(Type::String(string(&params[0])?.trim_end().to_string()))
+            })),
+        ),
+        (
+            "upper".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("upper", &params, 1)?;
+                Ok(Type::String(string(&params[0])?.to_uppercase()))
+            })),
+        ),
+        (
+            "lower".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("lower", &params, 1)?;
+                Ok(Type::String(string(&params[0])?.to_lowercase()))
+            })),
+        ),
+        (
+            "repeat".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("repeat", &params, 2)?;
+                let text = string(&params[0])?;
+                let count = size(&params[1])?;
+                check_length("repeat", text.chars().count().checked_mul(count))?;
+                Ok(Type::String(text.repeat(count)))
+            })),
+        ),
+        (
+            "pad-left".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("pad-left", &params, 2)?;
+                let text = string(&params[0])?;
+                let width = size(&params[1])?;
+                check_length("pad-left", Some(width))?;
+                let padding = width.saturating_sub(text.chars().count());
+                let mut result: String =
+                    std::iter::repeat_n(fill(params.get(2))?, padding).collect();
+                result.push_str(text);
+                Ok(Type::String(result))
+            })),
+        ),
+        (
+            "pad-right".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("pad-right", &params, 2)?;
+                let text = string(&params[0])?;
+                let width = size(&params[1])?;
+                check_length("pad-right", Some(width))?;
+                let padding = width.saturating_sub(text.chars().count());
+                let mut result = text.to_string();
+                result.extend(std::iter::repeat_n(fill(params.get(2))?, padding));
+                Ok(Type::String(result))
+            })),
+        ),
+        (
+            "join".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("join", &params, 1)?;
+                let Type::List(items) = &params[0] else {
+                    return Err(type_error("list", &params[0]));
+                };
+                let separator = match params.get(1) {
+                    Some(separator) => string(separator)?,
+                    None => "",
+                };
+                Ok(Type::String(
+                    items
+                        .iter()
+                        .map(|i| i.get_string())
+                        .collect::<Vec<String>>()
+                        .join(separator),
+                ))
+            })),
+        ),
+        (
+            "lines".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("lines", &params, 1)?;
+                Ok(strings(string(&params[0])?.lines()))
+            })),
+        ),
+        (
+            "words".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("words", &params, 1)?;
+                Ok(strings(string(&params[0])?.split_whitespace()))
+            })),
+        ),
+        (
+            "chars".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("chars", &params, 1)?;
+                Ok(Type::List(
+                    string(&params[0])?
+                        .chars()
+                        .map(|c| Type::String(c.to_string()))
+                        .collect(),
+                ))
+            })),
+        ),
+        (
+            "char-code".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("char-code", &params, 1)?;
+                let mut chars = string(&params[0])?.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Ok(Type::Integer(Int::Small(c as i64))),
+                    _ => Err(PravdaError::new(
+                        ErrorKind::Value,
+                        format!(
+                            "expected single character, but found {}",
+                            params[0].get_symbol()
+                        ),
+                    )),
+                }
+            })),
+        ),
+        (
+            "from-char-code".to_string(),
+            Type::Function(Function::BuiltIn(|params, _| {
+                check_arity("from-char-code", &params, 1)?;
+                // The codes are given as the arguments or the list
+                let codes = match params.as_slice() {
+                    [Type::List(codes)] => codes.clone(),
+                    _ => params.iter().cloned().collect::<Vector<Type>>(),
+                };
+                let mut result = String::new();
+                for code in codes {
+                    let c = number::integer(&code)?
+                        .to_i64()
+                        .and_then(|code| u32::try_from(code).ok())
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| {
+                            PravdaError::new(
+                                ErrorKind::Value,
+                                format!("{} is not valid character code", code.get_symbol()),
+                            )
+                        })?;
+                    result.push(c);
+                }
+                Ok(Type::String(result))
+            })),
+        ),
+    ]
+}
@@ -0,0 +1,732 @@
+//! Stack-based virtual machine that runs the compiled bytecode
+use crate::compiler::{compile, Capture, Op, Proto};
+use crate::dict::{Dict, Key};
+use crate::env::{Env, Shared};
+use crate::error::{ErrorKind, PravdaError};
+use crate::format::format_value;
+use crate::lexer::Span;
+use crate::parser::Stmt;
+use crate::set::to_set;
+use crate::{
+    call_function, clause_label, eval_code, load_path, match_clause, required_count, warn_at,
+    Depth, Flow, Function, Type, LAMBDA,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// User-defined function that's compiled to bytecode
+pub struct Closure {
+    /// Name to show in the traceback
+    pub name: String,
+    pub clauses: Vec<Clause>,
+}
+
+/// Clause of the function that's chosen by pattern matching
+#[derive(Clone)]
+pub struct Clause {
+    /// The argument patterns
+    pub patterns: Vec<Type>,
+    proto: Rc<Proto>,
+    /// Variables of the outer function that the clause captures
+    upvalues: Rc<Vec<Shared>>,
+    /// Global scope where the function is defined
+    env: Rc<Env>,
+    /// Arguments that are bound by partial application, and their slots
+    bound: Vec<(u32, Type)>,
+    /// How many patterns are already bound by partial application
+    offset: usize,
+}
+
+impl fmt::Debug for Closure {
+    // Clauses may capture the function itself, so don't show the contents
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Closure({}, {} clauses)", self.name, self.clauses.len())
+    }
+}
+
+/// Local variable of the frame
+enum Local {
+    Value(Option<Type>),
+    /// Variable that's captured by the closure or linked to the scope
+    Shared(Shared),
+}
+
+/// Where the lazy code goes back when it's finished
+struct Thunk {
+    ret: usize,
+    tail: bool,
+    invoke: u32,
+    span: Option<Span>,
+}
+
+/// Frame of the function calling
+struct Frame {
+    proto: Rc<Proto>,
+    upvalues: Rc<Vec<Shared>>,
+    locals: Vec<Local>,
+    env: Rc<Env>,
+    ip: usize,
+    /// Height of the stack when the frame is started
+    base: usize,
+    /// Lazy codes that are being evaluated in this frame
+    thunks: Vec<Thunk>,
+    /// How many times the result is called again without arguments
+    invoke: u32,
+    /// Name and clause to show in the traceback, it's none in the top level
+    function: Option<(String, Option<String>)>,
+    /// Where the function is called
+    call_site: Option<Span>,
+    /// Scope that's linked to the variables of the frame, to pass to the built-in functions
+    scope: Option<Rc<Env>>,
+    _depth: Option<Depth>,
+}
+
+impl Frame {
+    fn new(proto: Rc<Proto>, upvalues: Rc<Vec<Shared>>, env: Rc<Env>) -> Frame {
+        let locals = proto
+            .captured
+            .iter()
+            .map(|captured| {
+                if *captured {
+                    Local::Shared(Rc::new(RefCell::new(None)))
+                } else {
+                    Local::Value(None)
+                }
+            })
+            .collect();
+        Frame {
+            proto,
+            upvalues,
+            locals,
+            env,
+            ip: 0,
+            base: 0,
+            thunks: Vec::new(),
+            invoke: 0,
+            function: None,
+            call_site: None,
+            scope: None,
+            _depth: None,
+        }
+    }
+
+    fn get(&self, slot: u32) -> Option<Type> {
+        match &self.locals[slot as usize] {
+            Local::Value(value) => value.clone(),
+            Local::Shared(cell) => cell.borrow().clone(),
+        }
+    }
+
+    fn set(&mut self, slot: u32, value: Type) {
+        let previous = match &mut self.locals[slot as usize] {
+            Local::Value(local) => local.replace(value),
+            Local::Shared(cell) => cell.borrow_mut().replace(value),
+        };
+        // The scope may link another slot of the same name until this one is defined
+        if previous.is_none() {
+            self.scope = None;
+        }
+    }
+
+    /// Reset the variable, and the closures that captured the old one keep it
+    fn clear(&mut self, slot: u32) {
+        match &mut self.locals[slot as usize] {
+            Local::Value(local) => *local = None,
+            Local::Shared(cell) => *cell = Rc::new(RefCell::new(None)),
+        }
+        self.scope = None;
+    }
+}
+
+/// Result of entering the function
+enum Entered {
+    /// The function returned value without running, such as partial application
+    Value(Type),
+    Frame(Box<Frame>),
+}
+
+/// How the function is called
+struct Site {
+    span: Option<Span>,
+    tail: bool,
+    invoke: u32,
+}
+
+struct Vm {
+    stack: Vec<Type>,
+    frames: Vec<Frame>,
+}
+
+/// Compile the program and run it in the global scope
+/// # Arguments
+/// * `program` - Lines of the parsed program to run
+/// * `memory` - Has functions and variables to access in the program
+/// # Return values
+/// This functions returns value that's result of running
+pub fn run_program(program: &[Stmt], memory: &Rc<Env>) -> Result<Type, PravdaError> {
+    let frame = Frame::new(compile(program), Rc::new(Vec::new()), memory.clone());
+    Vm::new(frame).run()
+}
+
+/// Call the compiled function with the arguments
+pub fn call(closure: &Rc<Closure>, params: Vec<Type>) -> Result<Type, PravdaError> {
+    match enter(closure, params)? {
+        Entered::Value(value) => Ok(value),
+        Entered::Frame(frame) => Vm::new(*frame).run(),
+    }
+}
+
+/// Choose the clause and prepare the frame to run it
+fn enter(closure: &Rc<Closure>, params: Vec<Type>) -> Result<Entered, PravdaError> {
+    let index = match_clause(
+        closure.clauses.iter().map(|i| i.patterns.as_slice()),
+        &params,
+    )?;
+    let clause = &closure.clauses[index];
+
+    // Bind the arguments to the slots
+    let mut bound = clause.bound.clone();
+    for (index, pattern) in clause.patterns.iter().enumerate() {
+        let Type::Symbol(name) = pattern else {
+            continue;
+        };
+        let Some(Some(slot)) = clause.proto.params.get(clause.offset + index) else {
+            continue;
+        };
+        if name.starts_with('~') {
+            // Processing of mutable length argument
+            let rest = params[index.min(params.len())..params.len()]
+                .iter()
+                .cloned();
+            bound.push((*slot, Type::List(rest.collect())));
+        } else if let Some(value) = params.get(index) {
+            bound.push((*slot, value.clone()));
+        }
+    }
+
+    if required_count(&clause.patterns) <= params.len() {
+        let mut frame = Frame::new(
+            clause.proto.clone(),
+            clause.upvalues.clone(),
+            clause.env.clone(),
+        );
+        for (slot, value) in bound {
+            frame.set(slot, value);
+        }
+        frame.function = Some((
+            closure.name.clone(),
+            clause_label(&closure.name, &clause.patterns, closure.clauses.len()),
+        ));
+        Ok(Entered::Frame(Box::new(frame)))
+    } else if params.is_empty() {
+        // Calling without arguments gives the function itself
+        Ok(Entered::Value(Type::Function(Function::Compiled(
+            closure.clone(),
+        ))))
+    } else {
+        // Partial application of the function
+        let mut clauses = closure.clauses.clone();
+        clauses.push(Clause {
+            patterns: clause.patterns[params.len()..].to_vec(),
+            bound,
+            offset: clause.offset + params.len(),
+            ..clause.clone()
+        });
+        Ok(Entered::Value(Type::Function(Function::Compiled(Rc::new(
+            Closure {
+                name: closure.name.clone(),
+                clauses,
+            },
+        )))))
+    }
+}
+
+impl Vm {
+    fn new(frame: Frame) -> Vm {
+        Vm {
+            stack: Vec::new(),
+            frames: vec![frame],
+        }
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Run until the first frame returns, and record the frames that the error passes through
+    fn run(&mut self) -> Result<Type, PravdaError> {
+        self.execute().map_err(|mut err| {
+            while let Some(frame) = self.frames.pop() {
+                if let Some((name, clause)) = frame.function {
+                    err = err.in_frame(name, clause);
+                }
+                if let Some(span) = &frame.call_site {
+                    err = err.called_at(span);
+                }
+            }
+            err
+        })
+    }
+
+    fn execute(&mut self) -> Result<Type, PravdaError> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let op = frame.proto.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                Op::Constant(index) => {
+                    let value = frame.proto.constants[index as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::GetLocal(..) | Op::GetUpvalue(..) | Op::GetGlobal { .. } => {
+                    let value = self.load(op)?;
+                    self.stack.push(value);
+                }
+                Op::DefineLocal(slot) => {
+                    let value = self.stack.last().unwrap().clone();
+                    self.frame().set(slot, value);
+                }
+                Op::DefineGlobal(name) => {
+                    let value = self.stack.last().unwrap().clone();
+                    frame.env.define(&frame.proto.names[name as usize], value);
+                }
+                Op::ClearLocals(start, end) => {
+                    for slot in start..end {
+                        frame.clear(slot);
+                    }
+                }
+                Op::Pop => {
+                    self.stack.pop();
+                }
+                Op::List(count) => {
+                    let items = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack.push(Type::List(items.into()));
+                }
+                Op::Append => {
+                    let value = self.stack.pop().unwrap();
+                    if let Some(Type::List(items)) = self.stack.last_mut() {
+                        items.push_back(value);
+                    }
+                }
+                Op::Extend => {
+                    let value = self.stack.pop().unwrap();
+                    if let Some(Type::List(items)) = self.stack.last_mut() {
+                        items.extend(value.get_list());
+                    }
+                }
+                Op::Stringify { spec, span } => {
+                    let value = self.stack.pop().unwrap();
+                    let text = format_value(&value, frame.proto.specs[spec as usize].as_ref())
+                        .map_err(|err| err.at(&frame.proto.spans[span as usize]))?;
+                    self.stack.push(Type::String(text));
+                }
+                Op::Concat(count) => {
+                    let parts = self.stack.split_off(self.stack.len() - count as usize);
+                    let text = parts.iter().map(|i| i.get_string()).collect();
+                    self.stack.push(Type::String(text));
+                }
+                Op::Struct(shape) => {
+                    let names = &frame.proto.shapes[shape as usize];
+                    let values = self.stack.split_off(self.stack.len() - names.len());
+                    let fields = names.iter().cloned().zip(values).collect();
+                    self.stack.push(Type::Struct(fields));
+                }
+                Op::Dict { count, span } => {
+                    let items = self.stack.split_off(self.stack.len() - count as usize * 2);
+                    let mut dict = Dict::new();
+                    for (index, entry) in items.chunks(2).enumerate() {
+                        let key = Key::new(&entry[0])
+                            .map_err(|err| err.at(&frame.proto.spans[span as usize + index]))?;
+                        dict.insert(key, entry[1].clone());
+                    }
+                    self.stack.push(Type::Dict(dict));
+                }
+                Op::Set(span) => {
+                    let items = self.stack.pop().unwrap().get_list();
+                    let set =
+                        to_set(items).map_err(|err| err.at(&frame.proto.spans[span as usize]))?;
+                    self.stack.push(Type::Set(set));
+                }
+                Op::Closure {
+                    proto,
+                    args,
+                    name,
+                    clause,
+                    span,
+                } => {
+                    let object = self.closure(proto, args, name, clause, span)?;
+                    self.stack.push(object);
+                }
+                Op::Rename(name) => {
+                    // Define variable, and the lambda is named after it
+                    let name = &frame.proto.names[name as usize];
+                    match self.stack.last_mut() {
+                        Some(Type::Function(Function::Compiled(closure)))
+                            if closure.name == LAMBDA =>
+                        {
+                            *closure = Rc::new(Closure {
+                                name: name.clone(),
+                                clauses: closure.clauses.clone(),
+                            });
+                        }
+                        Some(Type::Function(Function::UserDefined(anonymous, _)))
+                            if anonymous == LAMBDA =>
+                        {
+                            *anonymous = name.clone();
+                        }
+                        _ => {}
+                    }
+                }
+                Op::JumpIfNotFunction(target) => {
+                    if !matches!(self.stack.last(), Some(Type::Function(_))) {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::Jump(target) => frame.ip = target as usize,
+                Op::Call {
+                    args,
+                    spread,
+                    tail,
+                    invoke,
+                    span,
+                } => {
+                    let params = if spread {
+                        self.stack.pop().unwrap().get_list().into_iter().collect()
+                    } else {
+                        self.stack.split_off(self.stack.len() - args as usize)
+                    };
+                    let Some(Type::Function(function)) = self.stack.pop() else {
+                        unreachable!()
+                    };
+                    let site = self.site(span, tail, invoke);
+                    self.call(function, params, site)?;
+                }
+                Op::Invoke { times, tail, span } => {
+                    if let Some(Type::Function(_)) = self.stack.last() {
+                        let Some(Type::Function(function)) = self.stack.pop() else {
+                            unreachable!()
+                        };
+                        let site = self.site(span, tail, times - 1);
+                        self.call(function, vec![], site)?;
+                    }
+                }
+                Op::EndThunk => {
+                    let thunk = frame.thunks.pop().unwrap();
+                    if thunk.tail {
+                        // The lazy code was in tail position, so the function returns
+                        frame.invoke += thunk.invoke;
+                        if let Some(value) = self.ret()? {
+                            return Ok(value);
+                        }
+                    } else {
+                        frame.ip = thunk.ret;
+                        let value = self.stack.pop().unwrap();
+                        let site = Site {
+                            span: thunk.span,
+                            tail: false,
+                            invoke: thunk.invoke,
+                        };
+                        self.finish(value, site)?;
+                    }
+                }
+                Op::Return => {
+                    if let Some(value) = self.ret()? {
+                        return Ok(value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// How the function is called by the instruction
+    /// The tail call in the lazy code is only in tail position if the code is too
+    fn site(&mut self, span: u32, tail: bool, invoke: u32) -> Site {
+        let frame = self.frame();
+        Site {
+            span: Some(frame.proto.spans[span as usize].clone()),
+            tail: tail && frame.thunks.last().is_none_or(|i| i.tail),
+            invoke,
+        }
+    }
+
+    /// Read the variable, and look up the outer scope if it's not defined yet
+    fn load(&mut self, op: Op) -> Result<Type, PravdaError> {
+        let frame = self.frame();
+        let (value, fallback) = match op {
+            Op::GetLocal(slot, fallback) => (frame.get(slot), fallback),
+            Op::GetUpvalue(index, fallback) => {
+                (frame.upvalues[index as usize].borrow().clone(), fallback)
+            }
+            Op::GetGlobal { name, callee } => {
+                let name = &frame.proto.names[name as usize];
+                if let Some(value) = frame.env.get(name) {
+                    return Ok(value);
+                }
+                // The file is loaded if it's called by the path
+                if let Some(span) = callee {
+                    let span = frame.proto.spans[span as usize].clone();
+                    let name = name.clone();
+                    if let Some(value) = load_path(&name, &span, &self.scope())? {
+                        return Ok(value);
+                    }
+                    return Ok(Type::Symbol(name));
+                }
+                return Ok(Type::Symbol(name.clone()));
+            }
+            _ => unreachable!(),
+        };
+        match value {
+            Some(value) => Ok(value),
+            None => {
+                let fallback = self.frame().proto.fallbacks[fallback as usize];
+                self.load(fallback)
+            }
+        }
+    }
+
+    /// Create the function, or add the clause to the existing one
+    fn closure(
+        &mut self,
+        proto: u32,
+        args: u32,
+        name: u32,
+        clause: bool,
+        span: u32,
+    ) -> Result<Type, PravdaError> {
+        let patterns = self.stack.split_off(self.stack.len() - args as usize);
+        let exist = if clause { self.stack.pop() } else { None };
+
+        let frame = self.frame();
+        let proto = frame.proto.protos[proto as usize].clone();
+        let upvalues = proto
+            .captures
+            .iter()
+            .map(|capture| match capture {
+                Capture::Local(slot) => match &frame.locals[*slot as usize] {
+                    Local::Shared(cell) => cell.clone(),
+                    Local::Value(_) => unreachable!(),
+                },
+                Capture::Upvalue(index) => frame.upvalues[*index as usize].clone(),
+            })
+            .collect();
+        let name = frame.proto.names[name as usize].clone();
+        let new = Clause {
+            patterns,
+            proto,
+            upvalues: Rc::new(upvalues),
+            env: frame.env.clone(),
+            bound: Vec::new(),
+            offset: 0,
+        };
+
+        let mut clauses = Vec::new();
+        if let Some(Type::Function(Function::Compiled(exist))) = exist {
+            if exist.clauses[0].patterns.len() != args as usize {
+                return Err(PravdaError::new(
+                    ErrorKind::Arity,
+                    "the function arguments length should be immutable",
+                )
+                .at(&frame.proto.spans[span as usize]));
+            }
+            // Add pattern match of the function
+            clauses = exist.clauses.clone();
+        }
+        clauses.push(new);
+        Ok(Type::Function(Function::Compiled(Rc::new(Closure {
+            name,
+            clauses,
+        }))))
+    }
+
+    /// Scope that has the variables of the current frame
+    /// The locals are moved into the cells that the scope links,
+    /// so that the built-in function like `reduce` can write them
+    fn scope(&mut self) -> Rc<Env> {
+        let frame = self.frame();
+        if let Some(scope) = &frame.scope {
+            return scope.clone();
+        }
+        if frame.locals.is_empty() && frame.upvalues.is_empty() {
+            return frame.env.clone();
+        }
+
+        let mut linked = HashMap::new();
+        for (name, cell) in frame.proto.upvalues.iter().zip(frame.upvalues.iter()) {
+            linked.insert(name.clone(), cell.clone());
+        }
+        for (local, name) in frame.locals.iter_mut().zip(frame.proto.locals.iter()) {
+            if let Local::Value(value) = local {
+                let value = value.take();
+                *local = Local::Shared(Rc::new(RefCell::new(value)));
+            }
+            let Local::Shared(cell) = local else {
+                unreachable!()
+            };
+            // The same name may have slots in several blocks, and the defined one is linked
+            if cell.borrow().is_some() || !linked.contains_key(name) {
+                linked.insert(name.clone(), cell.clone());
+            }
+        }
+        let scope = Env::linked(&frame.env, linked);
+        frame.scope = Some(scope.clone());
+        scope
+    }
+
+    /// Call the function, and the result is pushed when it's finished
+    fn call(
+        &mut self,
+        function: Function,
+        params: Vec<Type>,
+        site: Site,
+    ) -> Result<(), PravdaError> {
+        let located = |err: PravdaError| match &site.span {
+            Some(span) => err.called_at(span),
+            None => err,
+        };
+        match function {
+            Function::Compiled(closure) => match enter(&closure, params).map_err(located)? {
+                Entered::Value(value) => self.finish(value, site),
+                Entered::Frame(mut frame) if site.tail => {
+                    // Replace the current frame, so that tail call doesn't grow the stack
+                    let current = self.frame();
+                    frame.base = current.base;
+                    frame.invoke = current.invoke + site.invoke;
+                    frame.call_site = current.call_site.take();
+                    frame._depth = current._depth.take();
+                    let base = current.base;
+                    self.stack.truncate(base);
+                    *self.frames.last_mut().unwrap() = *frame;
+                    Ok(())
+                }
+                Entered::Frame(mut frame) => {
+                    frame._depth =
+                        Some(Depth::enter(&Function::Compiled(closure.clone())).map_err(located)?);
+                    frame.base = self.stack.len();
+                    frame.invoke = site.invoke;
+                    frame.call_site = site.span;
+                    self.frames.push(*frame);
+                    Ok(())
+                }
+            },
+            Function::Control(control) => {
+                // The control function doesn't access the variables by itself,
+                // so the scope is made only when it's needed
+                let env = self.frame().env.clone();
+                match control(params, &env).map_err(located)? {
+                    Flow::Value(value) => self.finish(value, site),
+                    Flow::Lookup(name) => {
+                        let value = self.scope().get(&name).unwrap_or(Type::Null);
+                        self.finish(value, site)
+                    }
+                    Flow::Eval(code) => {
+                        if let Some(start) = self.thunk(&code) {
+                            // Run the lazy code that's compiled in this function
+                            let frame = self.frame();
+                            frame.thunks.push(Thunk {
+                                ret: frame.ip,
+                                tail: site.tail,
+                                invoke: site.invoke,
+                                span: site.span,
+                            });
+                            frame.ip = start;
+                            Ok(())
+                        } else {
+                            let scope = self.scope();
+                            let value = eval_code(&code, &scope)?.resolve(&scope)?;
+                            self.finish(value, site)
+                        }
+                    }
+                }
+            }
+            Function::Scoped(function) => {
+                let scope = self.scope();
+                let result = function(params, &scope);
+                if let Some(span) = &site.span {
+                    warn_at(span);
+                }
+                self.finish(result.map_err(located)?, site)
+            }
+            function => {
+                // Other functions need the variables only if they call the function that accesses them
+                let scoped = params.iter().any(|param| {
+                    matches!(
+                        param,
+                        Type::Function(Function::Control(_) | Function::Scoped(_))
+                    )
+                });
+                let env = if scoped {
+                    self.scope()
+                } else {
+                    self.frame().env.clone()
+                };
+                let value = call_function(function, params, &env).map_err(located)?;
+                self.finish(value, site)
+            }
+        }
+    }
+
+    /// Push the result of the calling, or call it again without arguments
+    fn finish(&mut self, value: Type, site: Site) -> Result<(), PravdaError> {
+        match value {
+            Type::Function(function) if site.invoke > 0 => {
+                let site = Site {
+                    invoke: site.invoke - 1,
+                    ..site
+                };
+                self.call(function, vec![], site)
+            }
+            value => {
+                self.stack.push(value);
+                Ok(())
+            }
+        }
+    }
+
+    /// Where the lazy code starts, if it's compiled in the current function
+    /// The code is one of the function if it shares the node that the function holds,
+    /// so the node can't be freed and another code can't take its place
+    fn thunk(&mut self, code: &Type) -> Option<usize> {
+        let same = |thunk: &Type| match (thunk, code) {
+            (Type::Expr(a), Type::Expr(b)) => Rc::ptr_eq(a, b),
+            (Type::Block(a), Type::Block(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        };
+        let proto = &self.frame().proto;
+        proto
+            .thunks
+            .iter()
+            .find(|(thunk, _)| same(thunk))
+            .map(|(_, start)| *start as usize)
+    }
+
+    /// Return from the current function, and give the result if it's the first frame
+    fn ret(&mut self) -> Result<Option<Type>, PravdaError> {
+        let value = self.stack.pop().unwrap();
+        let frame = self.frame();
+        if frame.invoke > 0 {
+            if let Type::Function(function) = value {
+                // Call the result in place of this function, and return again
+                frame.invoke -= 1;
+                frame.ip -= 1;
+                let site = Site {
+                    span: frame.call_site.clone(),
+                    tail: true,
+                    invoke: 0,
+                };
+                self.call(function, vec![], site)?;
+                return Ok(None);
+            }
+        }
+
+        if self.frames.len() == 1 {
+            return Ok(Some(value));
+        }
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.base);
+        self.stack.push(value);
+        Ok(None)
+    }
+}
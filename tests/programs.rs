@@ -0,0 +1,130 @@
+//! Run the programs in `tests/programs` by both of the backends,
+//! and compare the outputs with the expected ones
+//!
+//! `<name>.out` is the expected standard output,
+//! and `<name>.err` is the expected standard error if it exists
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Directory that has the programs and their outputs
+fn directory() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/programs")
+}
+
+/// Command to run the program by the backend
+/// It runs in the empty directory, so that the program can make the files
+fn command(name: &str, backend: &str, args: &[&str]) -> Command {
+    let work = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}-{backend}"));
+    let _ = fs::remove_dir_all(&work);
+    fs::create_dir_all(&work).expect("failed to make the working directory");
+    let file = format!("{name}.pvd");
+    fs::copy(directory().join(&file), work.join(&file)).expect("failed to copy the program");
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_pravda"));
+    command
+        .current_dir(&work)
+        .args(["-b", backend])
+        .args(args)
+        .arg(file);
+    command
+}
+
+/// Run the program by the backend, and get the standard output and error
+fn run(name: &str, backend: &str, args: &[&str]) -> (String, String) {
+    let output = command(name, backend, args)
+        .output()
+        .expect("failed to run pravda");
+    (
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+/// Check that both of the backends output the expected ones
+fn check(name: &str, args: &[&str]) {
+    let expected = fs::read_to_string(directory().join(format!("{name}.out")))
+        .unwrap_or_else(|err| panic!("can't read the output of {name}: {err}"));
+    let error = fs::read_to_string(directory().join(format!("{name}.err"))).ok();
+    for backend in ["tree", "vm"] {
+        let (stdout, stderr) = run(name, backend, args);
+        assert_eq!(stdout, expected, "standard output of {name} on {backend}");
+        if let Some(error) = &error {
+            assert_eq!(&stderr, error, "standard error of {name} on {backend}");
+        }
+    }
+}
+
+/// Define the test for each program, and the arguments of the command line can be given
+macro_rules! programs {
+    ($($name:ident $([$($arg:literal),*])?),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                check(stringify!($name), &[$($($arg),*)?]);
+            }
+        )*
+    };
+}
+
+programs! {
+    syntax,
+    syntax_error,
+    runtime_error,
+    errors,
+    tail_calls,
+    closures,
+    local_functions,
+    scoping ["-r", "5000"],
+    reduce,
+    folds,
+    try_catch,
+    raise,
+    traceback,
+    recursion ["-r", "1500"],
+    integers,
+    numbers,
+    math,
+    clauses,
+    comments,
+    interpolation,
+    unclosed_comment,
+    literals,
+    bad_escape,
+    lists,
+    dicts,
+    persistent,
+    sets,
+    ordering,
+    strings,
+    regex,
+    random ["--seed", "42"],
+    files,
+}
+
+/// Peak memory of the program in kilobytes, that's measured by the OS when it exits
+#[cfg(target_os = "linux")]
+#[allow(clippy::zombie_processes)] // It's reaped by `wait4` that also gets the usage
+fn peak_memory(name: &str, backend: &str) -> i64 {
+    let child = command(name, backend, &[])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("failed to run pravda");
+    let pid = child.id() as libc::pid_t;
+    let mut status = 0;
+    // SAFETY: `rusage` is plain data, and the child is waited only here
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    assert_eq!(unsafe { libc::wait4(pid, &mut status, 0, &mut usage) }, pid);
+    usage.ru_maxrss
+}
+
+/// The scopes of the calls that define the local functions are freed after them,
+/// though it takes about 100MB if they leak
+#[cfg(target_os = "linux")]
+#[test]
+fn local_functions_memory() {
+    for backend in ["tree", "vm"] {
+        let peak = peak_memory("local_functions", backend);
+        assert!(peak < 40 * 1024, "peak memory on {backend} is {peak}KB");
+    }
+}